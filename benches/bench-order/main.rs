@@ -0,0 +1,54 @@
+use criterion::{BenchmarkId, criterion_group, criterion_main, Criterion};
+use tarcrush::order::graph::CandidateGraph;
+use tarcrush::shingleprint::shingleprint;
+use tarcrush::Frame;
+
+// Deterministic xorshift generator, so that synthetic inputs are reproducible.
+fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+  let mut state = seed | 1;
+  (0..len)
+    .map(|_| {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      state as u8
+    })
+    .collect()
+}
+
+// Synthetic frames in clusters of eight near-duplicates.
+fn frames(n: usize) -> Vec<Frame> {
+  (0..n)
+    .map(|i| {
+      let mut content = random_bytes(2 * (i / 8) as u64 + 1, 2048);
+      content[i % 2048] ^= 0xFF;
+      Frame {
+        bounds: 0..content.len(),
+        head_sp: shingleprint(&content[..1024], &Default::default()),
+        tail_sp: shingleprint(&content[1024..], &Default::default()),
+        barrier: false,
+      }
+    })
+    .collect()
+}
+
+fn bench_candidate_graph(c: &mut Criterion) {
+  let mut g = c.benchmark_group("candidate_graph");
+  g.sample_size(10);
+  for n in [256, 1024, 4096, 16384, 65536] {
+    let frames = frames(n);
+    // Quadratic; only practical for small inputs.
+    if n <= 4096 {
+      g.bench_with_input(BenchmarkId::new("all_pairs", n), &frames, |b, frames| {
+        b.iter(|| CandidateGraph::all_pairs(frames))
+      });
+    }
+    g.bench_with_input(BenchmarkId::new("lsh", n), &frames, |b, frames| {
+      b.iter(|| CandidateGraph::with_lsh(frames))
+    });
+  }
+  g.finish();
+}
+
+criterion_group!(benches, bench_candidate_graph);
+criterion_main!(benches);
@@ -2,22 +2,49 @@ use criterion::{BenchmarkId, Throughput, criterion_group, criterion_main, Criter
 use std::time::Duration;
 use tarcrush::shingleprint;
 
-const INPUTS: &'static [(&'static str, &'static [u8])] = &[
+const INPUTS: &[(&str, &[u8])] = &[
   ("binsort1kB", include_bytes!("input-binsort1kB.dat")),
   ("linux1MB", include_bytes!("input-linux1MB.dat")),
 ];
 
 fn bench_shingleprint(c: &mut Criterion) {
+  let config = shingleprint::ShingleprintConfig::default();
   let mut g = c.benchmark_group("shingleprint");
   g.measurement_time(Duration::from_secs(20));
   for &(input_name, input) in INPUTS {
     g.throughput(Throughput::Bytes(input.len() as u64));
     g.bench_with_input(BenchmarkId::new("portable", input_name), input, |b, input| {
-      b.iter(|| shingleprint::shingleprint_portable(input))
+      b.iter(|| shingleprint::shingleprint_portable(input, &config))
     });
+    g.bench_with_input(BenchmarkId::new("rolling", input_name), input, |b, input| {
+      b.iter(|| shingleprint::shingleprint_rolling(input, &config))
+    });
+    g.bench_with_input(BenchmarkId::new("builder", input_name), input, |b, input| {
+      b.iter(|| {
+        let mut builder = shingleprint::ShingleprintBuilder::new(&config);
+        input.chunks(4096).for_each(|chunk| builder.update(chunk));
+        builder.finish()
+      })
+    });
+    g.bench_with_input(BenchmarkId::new("xxh3", input_name), input, |b, input| {
+      b.iter(|| shingleprint::shingleprint_with::<shingleprint::hash::Xxh3>(input, &config))
+    });
+    #[cfg(target_arch = "x86_64")]
     if is_x86_feature_detected!("sse4.2") {
       g.bench_with_input(BenchmarkId::new("sse", input_name), input, |b, input| {
-        b.iter(|| unsafe { shingleprint::shingleprint_sse(input) })
+        b.iter(|| unsafe { shingleprint::shingleprint_sse(input, &config) })
+      });
+    }
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sse4.2") && is_x86_feature_detected!("avx2") {
+      g.bench_with_input(BenchmarkId::new("avx2", input_name), input, |b, input| {
+        b.iter(|| unsafe { shingleprint::shingleprint_avx2(input, &config) })
+      });
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("crc") {
+      g.bench_with_input(BenchmarkId::new("crc", input_name), input, |b, input| {
+        b.iter(|| unsafe { shingleprint::shingleprint_crc(input, &config) })
       });
     }
   }
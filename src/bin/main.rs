@@ -1,3 +1,291 @@
-fn main() -> Result<std::process::ExitCode, std::io::Error> {
-  todo!()
+use clap::{Args, Parser, Subcommand};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tarcrush::params::{Params, Target};
+use tarcrush::shingleprint::{self, ShingleprintConfig};
+use tarcrush::{analyze, compress, crush, index, ingress, restore, verify, Error};
+
+#[derive(Debug, Parser)]
+#[command(version, about, after_help = EXIT_STATUS_HELP)]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  success
+  1  verify found the crushed archive differs from the original
+  2  invalid usage, including an index built with different options
+  3  malformed archive or index
+  4  failure to read, write or compress
+  5  internal error";
+
+#[derive(Debug, Subcommand)]
+enum Command {
+  /// Reorder the records of a tar archive to better suit stream compression.
+  Crush {
+    /// Archive to read, which may be compressed; standard input if omitted or "-".
+    input: Option<PathBuf>,
+    /// Where to write the crushed archive; standard output if omitted or "-".
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Compressor the crushed archive will be fed to (gzip, bzip2, zstd or
+    /// xz), whose window determines how far apart similar records may be.
+    /// Defaults to the codec given to --compress, or else gzip.
+    #[arg(short, long)]
+    target: Option<Target>,
+    /// Compress the crushed archive with gzip, bzip2, zstd or xz.
+    #[arg(short, long)]
+    compress: Option<Target>,
+    /// Compression level; the codec's usual default if omitted.
+    #[arg(short, long, requires = "compress")]
+    level: Option<u32>,
+    /// Number of threads to compress with, for zstd and xz; all available
+    /// processors if omitted.
+    #[arg(short = 'T', long, requires = "compress", value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+    /// Index written by "tarcrush index" for an earlier version of the
+    /// archive, whose shingleprints are reused for unchanged records.
+    #[arg(long)]
+    index: Option<PathBuf>,
+    #[command(flatten)]
+    shingleprint: ShingleprintArgs,
+  },
+  /// Scan an archive and write an index of its records, so that later
+  /// crushes of the same or a similar archive can skip rehashing them.
+  Index {
+    /// Archive to read, which may be compressed; standard input if omitted or "-".
+    input: Option<PathBuf>,
+    /// Where to write the index; standard output if omitted or "-".
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Compressor the archive will be crushed for (gzip, bzip2, zstd or xz).
+    /// An index can only be reused when crushing for the same target.
+    #[arg(short, long, default_value_t)]
+    target: Target,
+    /// Write the index as JSON, for inspection, rather than in the binary
+    /// format that crush reads.
+    #[arg(long)]
+    json: bool,
+    /// Earlier index whose shingleprints are reused for unchanged records.
+    #[arg(long)]
+    index: Option<PathBuf>,
+    #[command(flatten)]
+    shingleprint: ShingleprintArgs,
+  },
+  /// Compress an archive with and without crushing it, and compare the results.
+  Analyze {
+    /// Archive to read, which may be compressed; standard input if omitted or "-".
+    input: Option<PathBuf>,
+    /// Compressor to try: gzip, bzip2, zstd or xz.
+    #[arg(short, long, default_value_t)]
+    codec: Target,
+    /// Compression level; the codec's usual default if omitted.
+    #[arg(short, long)]
+    level: Option<u32>,
+    #[command(flatten)]
+    shingleprint: ShingleprintArgs,
+  },
+  /// Recreate the original archive, byte for byte, from a crushed archive.
+  /// If the original was compressed, it is recreated uncompressed.
+  Restore {
+    /// Crushed archive to read, which may be compressed; standard input if omitted or "-".
+    input: Option<PathBuf>,
+    /// Where to write the uncompressed original archive; standard output if omitted or "-".
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+  },
+  /// Check that a crushed archive holds exactly the members of the original,
+  /// and restores to it byte for byte once both are decompressed. Reports the
+  /// first divergence found.
+  Verify {
+    /// Original archive, which may be compressed; standard input if "-".
+    original: PathBuf,
+    /// Crushed archive, which may be compressed; standard input if "-".
+    crushed: PathBuf,
+  },
+}
+
+#[derive(Debug, Args)]
+struct ShingleprintArgs {
+  /// Length in bytes of the substrings whose hashes characterise records.
+  /// Shorter suits text; longer suits binary data.
+  #[arg(long, default_value_t = shingleprint::SHINGLE_LEN)]
+  shingle_len: usize,
+  /// Number of hashes kept per record. More find similar records more
+  /// reliably, but take more time and memory.
+  #[arg(long, default_value_t = shingleprint::SHINGLEPRINT_FEATURES)]
+  features: usize,
+}
+
+impl ShingleprintArgs {
+  fn params(&self, target: Target) -> Result<Params, shingleprint::ConfigError> {
+    let shingleprint = ShingleprintConfig::new(self.shingle_len, self.features)?;
+    Ok(Params { shingleprint, ..Params::for_target(target) })
+  }
+}
+
+// Why a command failed, which determines the exit status.
+enum Failure {
+  // Options that clap can't check by itself were invalid.
+  Usage(Box<dyn std::error::Error>),
+  Error(Error),
+}
+
+impl From<Error> for Failure {
+  fn from(err: Error) -> Self {
+    Failure::Error(err)
+  }
+}
+
+fn usage(err: impl Into<Box<dyn std::error::Error>>) -> Failure {
+  Failure::Usage(err.into())
+}
+
+fn main() -> ExitCode {
+  let result = run(Cli::parse().command);
+  let (err, status): (&dyn std::error::Error, u8) = match &result {
+    Ok(()) => return ExitCode::SUCCESS,
+    Err(Failure::Usage(err)) => (err.as_ref(), EXIT_USAGE),
+    Err(Failure::Error(err)) => (err, exit_status(err)),
+  };
+  let chain: Vec<String> = std::iter::successors(Some(err), |err| err.source()).map(ToString::to_string).collect();
+  eprintln!("tarcrush: {}", chain.join(": "));
+  ExitCode::from(status)
+}
+
+fn run(command: Command) -> Result<(), Failure> {
+  match command {
+    Command::Crush { input, output, target, compress: None, index, shingleprint, .. } => {
+      let params = shingleprint.params(target.unwrap_or_default()).map_err(usage)?;
+      let cache = load_cache(index.as_deref(), &params)?;
+      let mut out = open_output(stdio_if_dash(output).as_deref())?;
+      with_input(stdio_if_dash(input).as_deref(), |strategy| {
+        crush::crush_with_cache(strategy, &mut out, &params, cache.as_ref())
+      })?;
+    }
+    Command::Crush { input, output, target, compress: Some(codec), level, threads, index, shingleprint } => {
+      let level = level.unwrap_or(compress::default_level(codec));
+      compress::check_level(codec, level).map_err(usage)?;
+      let params = shingleprint.params(target.unwrap_or(codec)).map_err(usage)?;
+      let cache = load_cache(index.as_deref(), &params)?;
+      let threads = threads.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, |n| u32::try_from(n.get()).unwrap_or(u32::MAX))
+      });
+      let out = open_output(stdio_if_dash(output).as_deref())?;
+      let mut out = compress::Encoder::with_threads(codec, level, threads, out).map_err(Error::Compression)?;
+      with_input(stdio_if_dash(input).as_deref(), |strategy| {
+        crush::crush_with_cache(strategy, &mut out, &params, cache.as_ref())
+      })?;
+      out.finish().and_then(|mut out| out.flush()).map_err(Error::EgressIO)?;
+    }
+    Command::Index { input, output, target, json, index, shingleprint } => {
+      let params = shingleprint.params(target).map_err(usage)?;
+      let cache = load_cache(index.as_deref(), &params)?;
+      let mut out = open_output(stdio_if_dash(output).as_deref())?;
+      with_input(stdio_if_dash(input).as_deref(), |strategy| {
+        let index = index::build_index(strategy, &params, cache.as_ref())?;
+        let write = if json { index::write_index_json } else { index::write_index };
+        write(&index, &mut out).map_err(Error::EgressIO)
+      })?;
+    }
+    Command::Analyze { input, codec, level, shingleprint } => {
+      let level = level.unwrap_or(compress::default_level(codec));
+      let params = shingleprint.params(codec).map_err(usage)?;
+      let report =
+        with_input(stdio_if_dash(input).as_deref(), |strategy| analyze::analyze(strategy, &params, codec, level))?;
+      print_report(&report, codec, level);
+    }
+    Command::Restore { input, output } => {
+      let mut out = open_output(stdio_if_dash(output).as_deref())?;
+      with_content(stdio_if_dash(input).as_deref(), |content| restore::restore(content, &mut out))?;
+    }
+    Command::Verify { original, crushed } => {
+      let (original, crushed) = (stdio_if_dash(Some(original)), stdio_if_dash(Some(crushed)));
+      if original.is_none() && crushed.is_none() {
+        return Err(usage("only one archive can be read from standard input"));
+      }
+      with_content(original.as_deref(), |original| {
+        with_content(crushed.as_deref(), |crushed| verify::verify(original, crushed))
+      })?;
+    }
+  }
+  Ok(())
+}
+
+// Exit statuses, so that scripts can tell kinds of failure apart. These are
+// listed in the --help text too.
+const EXIT_DIVERGENCE: u8 = 1;
+const EXIT_USAGE: u8 = 2; // as for usage errors detected by clap
+const EXIT_MALFORMED: u8 = 3;
+const EXIT_IO: u8 = 4;
+const EXIT_INTERNAL: u8 = 5;
+
+fn exit_status(err: &Error) -> u8 {
+  match err {
+    Error::Divergence(_) => EXIT_DIVERGENCE,
+    Error::IncompatibleIndex => EXIT_USAGE,
+    Error::MalformedInput(_) | Error::MalformedCrushed(_) | Error::MalformedIndex(..) => EXIT_MALFORMED,
+    Error::IngressIO(_) | Error::IndexIO(_) | Error::SpoolIO(_) | Error::EgressIO(_) | Error::Compression(_) => EXIT_IO,
+    Error::CompanionThreadDied => EXIT_INTERNAL,
+  }
+}
+
+// "-" conventionally refers to standard input/output.
+fn stdio_if_dash(path: Option<PathBuf>) -> Option<PathBuf> {
+  path.filter(|path| path.as_os_str() != "-")
+}
+
+fn with_input<T>(
+  path: Option<&Path>,
+  callback: impl for<'a> FnOnce(&'a mut (dyn ingress::Strategy + Send)) -> Result<T, Error>,
+) -> Result<T, Error> {
+  match path {
+    Some(path) => ingress::from_path(path, callback),
+    None => ingress::from_stdin(callback),
+  }
+}
+
+fn with_content<T>(path: Option<&Path>, callback: impl FnOnce(&[u8]) -> Result<T, Error>) -> Result<T, Error> {
+  match path {
+    Some(path) => ingress::content_from_path(path, callback),
+    None => ingress::content_from_stdin(callback),
+  }
+}
+
+fn load_cache(index: Option<&Path>, params: &Params) -> Result<Option<index::Cache>, Error> {
+  let Some(path) = index else { return Ok(None) };
+  // The index is read the same way as an input, but is reported as the index.
+  let index = ingress::content_from_path(path, index::read_index).map_err(|err| match err {
+    Error::IngressIO(err) => Error::IndexIO(err),
+    err => err,
+  })?;
+  index::Cache::new(&index, params).map(Some)
+}
+
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>, Error> {
+  Ok(match path {
+    Some(path) => Box::new(BufWriter::new(File::create(path).map_err(Error::EgressIO)?)),
+    None => Box::new(BufWriter::new(std::io::stdout().lock())),
+  })
+}
+
+fn print_report(report: &analyze::Report, codec: Target, level: u32) {
+  let label = format!("{codec} -{level}");
+  println!("{label:<8} {:>12} {:>12} {:>7} {:>9}", "size", "compressed", "ratio", "time");
+  for (name, trial) in [("original", &report.original), ("crushed", &report.crushed)] {
+    println!(
+      "{name:<8} {:>12} {:>12} {:>6.2}% {:>8.2}s",
+      trial.len,
+      trial.compressed_len,
+      trial.ratio() * 100.0,
+      trial.duration.as_secs_f64(),
+    );
+  }
+  let savings = report.savings();
+  let percent = savings as f64 / report.original.compressed_len as f64 * 100.0;
+  println!("savings  {savings} bytes ({percent:.2}% of compressed original)");
 }
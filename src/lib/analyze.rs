@@ -0,0 +1,140 @@
+use crate::compress::Encoder;
+use crate::crush::crush;
+use crate::ingress::Strategy;
+use crate::params::{Params, Target};
+use crate::Error;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+// The outcome of compressing one stream.
+#[derive(Clone, Debug)]
+pub struct Trial {
+  pub len: u64,
+  pub compressed_len: u64,
+  pub duration: Duration,
+}
+
+impl Trial {
+  // Compressed length as a fraction of the uncompressed length; zero for an
+  // empty stream, which has no meaningful ratio.
+  pub fn ratio(&self) -> f64 {
+    if self.len == 0 {
+      0.0
+    } else {
+      self.compressed_len as f64 / self.len as f64
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Report {
+  pub original: Trial,
+  // Includes the restore trailer, and for its duration, the time taken to crush.
+  pub crushed: Trial,
+}
+
+impl Report {
+  // Bytes saved by crushing before compressing; negative if crushing hurt.
+  pub fn savings(&self) -> i64 {
+    self.original.compressed_len as i64 - self.crushed.compressed_len as i64
+  }
+}
+
+// Compresses both the archive provided by the ingress strategy and its
+// crushed counterpart with the given codec, discarding the output. The
+// archive is crushed with the given params, which are normally those for the
+// same codec.
+pub fn analyze(
+  strategy: &mut (dyn Strategy + Send),
+  params: &Params,
+  target: Target,
+  level: u32,
+) -> Result<Report, Error> {
+  let start = Instant::now();
+  let mut out = Counting::new(encoder(target, level)?);
+  crush(strategy, &mut out, params).map_err(|err| match err {
+    // Only the encoder could have failed to write.
+    Error::EgressIO(err) => Error::Compression(err),
+    err => err,
+  })?;
+  let crushed = trial(out, start)?;
+
+  let start = Instant::now();
+  let mut out = Counting::new(encoder(target, level)?);
+  out.write_all(strategy.content()).map_err(Error::Compression)?;
+  let original = trial(out, start)?;
+  Ok(Report { original, crushed })
+}
+
+fn encoder(target: Target, level: u32) -> Result<Encoder<Counting<io::Sink>>, Error> {
+  Encoder::new(target, level, Counting::new(io::sink())).map_err(Error::Compression)
+}
+
+fn trial(out: Counting<Encoder<Counting<io::Sink>>>, start: Instant) -> Result<Trial, Error> {
+  let compressed = out.inner.finish().map_err(Error::Compression)?;
+  Ok(Trial { len: out.len, compressed_len: compressed.len, duration: start.elapsed() })
+}
+
+// Counts the bytes written through it.
+#[derive(Debug)]
+struct Counting<W> {
+  inner: W,
+  len: u64,
+}
+
+impl<W> Counting<W> {
+  fn new(inner: W) -> Self {
+    Self { inner, len: 0 }
+  }
+}
+
+impl<W: Write> Write for Counting<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = self.inner.write(buf)?;
+    self.len += n as u64;
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ingress::MapStrategy;
+  use crate::util::testing::{member, random_bytes, END_OF_ARCHIVE};
+
+  #[test]
+  fn test_analyze() {
+    // Pairs of identical members, each too far from its twin for gzip to
+    // notice the repetition.
+    let contents: Vec<_> = (0..4).map(|i| random_bytes(2 * i + 1, 10000)).collect();
+    let mut archive: Vec<u8> =
+      (0..8).flat_map(|i| member(format!("file{i}").as_bytes(), b'0', &contents[i % 4])).collect();
+    archive.extend_from_slice(&END_OF_ARCHIVE);
+    let report = analyze(&mut MapStrategy::new(&archive), &Params::default(), Target::Gzip, 6).unwrap();
+    assert_eq!(report.original.len, archive.len() as u64);
+    // The crushed stream also holds the restore trailer.
+    assert!(report.crushed.len > archive.len() as u64);
+    assert!(report.original.ratio() > 0.9);
+    assert!(report.crushed.ratio() < 0.6, "{report:?}");
+    assert!(report.savings() > 0);
+  }
+
+  #[test]
+  fn test_empty() {
+    let report = analyze(&mut MapStrategy::new(b""), &Params::default(), Target::Gzip, 6).unwrap();
+    assert_eq!(report.original.len, 0);
+    assert!(report.original.compressed_len > 0);
+    assert_eq!(report.original.ratio(), 0.0);
+    assert!(report.crushed.ratio().is_finite());
+  }
+
+  #[test]
+  fn test_bad_level() {
+    let result = analyze(&mut MapStrategy::new(&END_OF_ARCHIVE), &Params::for_target(Target::Xz), Target::Xz, 10);
+    assert!(matches!(result, Err(Error::Compression(_))), "{result:?}");
+  }
+}
@@ -0,0 +1,234 @@
+use crate::params::Target;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::RangeInclusive;
+
+// Compression levels accepted for each codec.
+pub fn levels(target: Target) -> RangeInclusive<u32> {
+  match target {
+    Target::Gzip => 0..=9,
+    Target::Bzip2 => 1..=9,
+    Target::Zstd => 1..=22,
+    Target::Xz => 0..=9,
+  }
+}
+
+// The level each codec's command-line tool uses by default.
+pub fn default_level(target: Target) -> u32 {
+  match target {
+    Target::Gzip => 6,
+    Target::Bzip2 => 9,
+    Target::Zstd => 3,
+    Target::Xz => 6,
+  }
+}
+
+// A compressing writer for any of the supported codecs.
+pub enum Encoder<W: Write> {
+  Gzip(flate2::write::GzEncoder<W>),
+  Bzip2(bzip2::write::BzEncoder<W>),
+  Zstd(zstd::Encoder<'static, W>),
+  Xz(xz2::write::XzEncoder<W>),
+}
+
+pub fn check_level(target: Target, level: u32) -> io::Result<()> {
+  let levels = levels(target);
+  if levels.contains(&level) {
+    Ok(())
+  } else {
+    Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("{target} level must be between {} and {}", levels.start(), levels.end()),
+    ))
+  }
+}
+
+impl<W: Write> Encoder<W> {
+  pub fn new(target: Target, level: u32, out: W) -> io::Result<Self> {
+    Self::with_threads(target, level, 1, out)
+  }
+
+  // Compresses on up to the given number of threads, for codecs that support
+  // it (zstd and xz). Multithreaded xz splits its output into independently
+  // compressed blocks, which costs a little in compression ratio.
+  pub fn with_threads(target: Target, level: u32, threads: u32, out: W) -> io::Result<Self> {
+    check_level(target, level)?;
+    Ok(match target {
+      Target::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(out, flate2::Compression::new(level))),
+      Target::Bzip2 => Encoder::Bzip2(bzip2::write::BzEncoder::new(out, bzip2::Compression::new(level))),
+      Target::Zstd => {
+        let mut encoder = zstd::Encoder::new(out, level as i32)?;
+        if threads > 1 {
+          encoder.multithread(threads)?;
+        }
+        Encoder::Zstd(encoder)
+      }
+      Target::Xz if threads > 1 => {
+        let stream = xz2::stream::MtStreamBuilder::new()
+          .threads(threads)
+          .preset(level)
+          .check(xz2::stream::Check::Crc64)
+          .encoder()?;
+        Encoder::Xz(xz2::write::XzEncoder::new_stream(out, stream))
+      }
+      Target::Xz => Encoder::Xz(xz2::write::XzEncoder::new(out, level)),
+    })
+  }
+
+  // Writes out any buffered data and the end of the compressed stream.
+  pub fn finish(self) -> io::Result<W> {
+    match self {
+      Encoder::Gzip(encoder) => encoder.finish(),
+      Encoder::Bzip2(encoder) => encoder.finish(),
+      Encoder::Zstd(encoder) => encoder.finish(),
+      Encoder::Xz(encoder) => encoder.finish(),
+    }
+  }
+
+  fn inner(&mut self) -> &mut dyn Write {
+    match self {
+      Encoder::Gzip(encoder) => encoder,
+      Encoder::Bzip2(encoder) => encoder,
+      Encoder::Zstd(encoder) => encoder,
+      Encoder::Xz(encoder) => encoder,
+    }
+  }
+}
+
+impl<W: Write> Write for Encoder<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.inner().write(buf)
+  }
+
+  fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+    self.inner().write_all(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner().flush()
+  }
+}
+
+// Length of input needed for sniff to recognise any codec.
+pub const MAGIC_LEN: usize = 10;
+
+// Identifies the codec a stream was compressed with from its first
+// MAGIC_LEN bytes, if it's one of those supported.
+pub fn sniff(prefix: &[u8]) -> Option<Target> {
+  // bzip2 streams continue with a block header or, if empty, the end-of-stream
+  // marker. Checking for these rules out tar members that happen to be named "BZh1" etc.
+  let bzip2_block = |magic: &[u8]| magic.starts_with(&[0x31, 0x41, 0x59, 0x26, 0x53, 0x59]);
+  let bzip2_end = |magic: &[u8]| magic.starts_with(&[0x17, 0x72, 0x45, 0x38, 0x50, 0x90]);
+  match prefix {
+    // Also requiring the DEFLATE compression method.
+    [0x1f, 0x8b, 0x08, ..] => Some(Target::Gzip),
+    [b'B', b'Z', b'h', b'1'..=b'9', magic @ ..] if bzip2_block(magic) || bzip2_end(magic) => Some(Target::Bzip2),
+    [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Target::Zstd),
+    [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Target::Xz),
+    _ => None,
+  }
+}
+
+// A decompressing reader for any of the supported codecs. Like the codecs'
+// command-line tools, it decodes concatenated streams as one.
+pub enum Decoder<R: BufRead> {
+  Gzip(flate2::bufread::MultiGzDecoder<R>),
+  Bzip2(bzip2::bufread::MultiBzDecoder<R>),
+  Zstd(zstd::Decoder<'static, R>),
+  Xz(xz2::bufread::XzDecoder<R>),
+}
+
+impl<R: BufRead> Decoder<R> {
+  pub fn new(target: Target, src: R) -> io::Result<Self> {
+    Ok(match target {
+      Target::Gzip => Decoder::Gzip(flate2::bufread::MultiGzDecoder::new(src)),
+      Target::Bzip2 => Decoder::Bzip2(bzip2::bufread::MultiBzDecoder::new(src)),
+      Target::Zstd => Decoder::Zstd(zstd::Decoder::with_buffer(src)?),
+      Target::Xz => Decoder::Xz(xz2::bufread::XzDecoder::new_multi_decoder(src)),
+    })
+  }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      Decoder::Gzip(decoder) => decoder.read(buf),
+      Decoder::Bzip2(decoder) => decoder.read(buf),
+      Decoder::Zstd(decoder) => decoder.read(buf),
+      Decoder::Xz(decoder) => decoder.read(buf),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::testing::random_bytes;
+  use std::io::Read;
+
+  fn compress(target: Target, input: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new(target, default_level(target), Vec::new()).unwrap();
+    encoder.write_all(input).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  fn decompress(target: Target, compressed: &[u8]) -> Vec<u8> {
+    let mut decompressed = Vec::new();
+    Decoder::new(target, compressed).unwrap().read_to_end(&mut decompressed).unwrap();
+    decompressed
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let input = [random_bytes(1, 10000), vec![0; 10000], random_bytes(1, 10000)].concat();
+    for target in Target::ALL {
+      for level in [*levels(target).start(), default_level(target)] {
+        let mut encoder = Encoder::new(target, level, Vec::new()).unwrap();
+        encoder.write_all(&input).unwrap();
+        let compressed = encoder.finish().unwrap();
+        // Level 0 only stores its input.
+        assert!(level == 0 || compressed.len() < input.len(), "{target} level {level}");
+        assert_eq!(decompress(target, &compressed), input, "{target} level {level}");
+      }
+    }
+  }
+
+  #[test]
+  fn test_multithreaded() {
+    let input: Vec<u8> = (0..16).flat_map(|i| random_bytes(2 * (i % 8) + 1, 100000)).collect();
+    for target in Target::ALL {
+      let mut encoder = Encoder::with_threads(target, 1, 4, Vec::new()).unwrap();
+      encoder.write_all(&input).unwrap();
+      let compressed = encoder.finish().unwrap();
+      assert_eq!(decompress(target, &compressed), input, "{target}");
+    }
+  }
+
+  #[test]
+  fn test_bad_level() {
+    for target in Target::ALL {
+      assert!(levels(target).contains(&default_level(target)));
+      let err = Encoder::new(target, levels(target).end() + 1, Vec::new()).err().unwrap();
+      assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+    assert!(Encoder::new(Target::Bzip2, 0, Vec::new()).is_err());
+  }
+
+  #[test]
+  fn test_sniff() {
+    for target in Target::ALL {
+      assert_eq!(sniff(&compress(target, b"")), Some(target));
+      assert_eq!(sniff(&compress(target, &random_bytes(1, 1000))[..MAGIC_LEN]), Some(target));
+    }
+    assert_eq!(sniff(b""), None);
+    assert_eq!(sniff(&crate::util::testing::member(b"BZh9.txt", b'0', b"")), None);
+    assert_eq!(sniff(&crate::util::testing::END_OF_ARCHIVE), None);
+  }
+
+  #[test]
+  fn test_concatenated_streams() {
+    for target in Target::ALL {
+      let compressed = [compress(target, b"hello, "), compress(target, b"world")].concat();
+      assert_eq!(decompress(target, &compressed), b"hello, world", "{target}");
+    }
+  }
+}
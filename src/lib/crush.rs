@@ -0,0 +1,199 @@
+use crate::index::Cache;
+use crate::ingress::Strategy;
+use crate::order::{self, constraints::Constraints};
+use crate::params::Params;
+use crate::restore;
+use crate::Error;
+use std::io::Write;
+
+// Scans the archive provided by the ingress strategy, and writes its frames
+// to out in an order better suited to stream compression. The output is a
+// valid tar archive with the same members as the input, followed by the data
+// needed to restore the original archive exactly (see the restore module).
+pub fn crush(strategy: &mut (dyn Strategy + Send), out: &mut dyn Write, params: &Params) -> Result<(), Error> {
+  crush_with_cache(strategy, out, params, None)
+}
+
+// As crush, but taking shingleprints from the cache (built from an index of
+// an earlier version of the archive) for frames it has.
+pub fn crush_with_cache(
+  strategy: &mut (dyn Strategy + Send),
+  out: &mut dyn Write,
+  params: &Params,
+  cache: Option<&Cache>,
+) -> Result<(), Error> {
+  let frames = strategy.scan_sorted(params, cache)?;
+  let content = strategy.content();
+  let constraints = Constraints::new(&frames, content);
+  let permutation = order::order(&frames, params, &constraints);
+  for &i in &permutation {
+    out.write_all(&content[frames[i].bounds.clone()]).map_err(Error::EgressIO)?;
+  }
+  let crushed_bounds = permutation.iter().map(|&i| frames[i].bounds.clone());
+  restore::write_trailer(out, content, crushed_bounds).map_err(Error::EgressIO)?;
+  out.flush().map_err(Error::EgressIO)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ingress::MapStrategy;
+  use crate::Frame;
+  use crate::util::testing::{entry, member, random_bytes, END_OF_ARCHIVE};
+  use std::collections::HashMap;
+  use std::fs;
+  use std::io;
+  use std::os::unix::fs::MetadataExt;
+  use std::path::Path;
+  use std::process::{Command, Stdio};
+
+  #[test]
+  fn test_crush() {
+    let members = [
+      member(b"a.txt", b'0', b"The quick brown fox jumps over the lazy dog."),
+      [member(b"././@LongLink", b'L', b"x/long/name"), member(b"x/long/nam", b'0', b"")].concat(),
+      member(b"b.txt", b'0', b"Lorem ipsum dolor sit amet, consectetur adipiscing elit."),
+      member(b"c.txt", b'0', b"The quick brown fox jumps over the lazy cat."),
+    ];
+    let mut archive = members.concat();
+    // Archives are commonly padded out to a multiple of 10240 bytes.
+    archive.extend_from_slice(&[0; 10240 - 4096]);
+    let mut out = Vec::new();
+    crush(&mut MapStrategy::new(&archive), &mut out, &Params::default()).unwrap();
+
+    // Output must consist of the same frames, in some order, followed by
+    // the end-of-archive marker.
+    let eoa = members.iter().map(Vec::len).sum::<usize>();
+    assert_eq!(&out[eoa..eoa + END_OF_ARCHIVE.len()], END_OF_ARCHIVE);
+    let mut rest = &out[..eoa];
+    let mut seen = [false; 4];
+    while !rest.is_empty() {
+      let i = members.iter().position(|m| rest.starts_with(m)).expect("unrecognised frame");
+      assert!(!seen[i]);
+      seen[i] = true;
+      rest = &rest[members[i].len()..];
+    }
+    assert_eq!(seen, [true; 4]);
+  }
+
+  #[test]
+  fn test_crush_malformed() {
+    let archive = member(b"a.txt", b'0', &[b'x'; 1000]);
+    let result = crush(&mut MapStrategy::new(&archive[..1000]), &mut Vec::new(), &Params::default());
+    assert!(matches!(result, Err(Error::MalformedInput(..))));
+  }
+
+  #[test]
+  fn test_crush_extracts_the_same() {
+    assert!(has_gnu_tar(), "this test needs GNU tar as `tar` on the PATH");
+    let dup = random_bytes(2, 8192);
+    let members = [
+      // Ends with the content of the last dup, so would draw it forward.
+      member(b"bait", b'0', &[random_bytes(1, 8192), dup.clone()].concat()),
+      entry(b"dir/", b'5', 0o750, b"", b""),
+      member(b"dir/a", b'0', &random_bytes(3, 4096)),
+      member(b"dup", b'0', &random_bytes(4, 8192)),
+      member(b"target", b'0', &random_bytes(5, 4096)),
+      entry(b"dir/link", b'1', 0o644, b"target", b""),
+      member(b"pax_global_header", b'g', b"20 mtime=1234567890\n"),
+      entry(b"dir/sub/", b'5', 0o700, b"", b""),
+      member(b"dir/sub/b", b'0', &random_bytes(6, 4096)),
+      member(b"dup", b'0', &dup),
+      entry(b"dup-link", b'1', 0o644, b"dup", b""),
+      entry(b"symlink", b'2', 0o777, b"dir/sub/b", b""),
+    ];
+    let mut archive = members.concat();
+    archive.extend_from_slice(&[0; 1024]);
+    let (ok, original) = extract(&archive);
+    assert!(ok);
+
+    let mut crushed = Vec::new();
+    crush(&mut MapStrategy::new(&archive), &mut crushed, &Params::default()).unwrap();
+    let (ok, tree) = extract(&crushed);
+    assert!(ok);
+    assert_eq!(tree, original);
+
+    // Members after the end-of-archive marker aren't extracted, so must stay
+    // after it. That includes the trailer of an already crushed archive.
+    let appended = [&archive[..], &member(b"after", b'0', b"not extracted"), &END_OF_ARCHIVE].concat();
+    for archive in [appended, crushed] {
+      let mut recrushed = Vec::new();
+      crush(&mut MapStrategy::new(&archive), &mut recrushed, &Params::default()).unwrap();
+      let (ok, tree) = extract(&recrushed);
+      assert!(ok);
+      assert_eq!(tree, original);
+    }
+
+    // Whereas the same ordering without constraints would not do.
+    let frames = scan(&archive);
+    let permutation = order::order(&frames, &Params::default(), &Constraints::none(frames.len()));
+    let mut unconstrained: Vec<u8> = permutation.iter().flat_map(|&i| &archive[frames[i].bounds.clone()]).copied().collect();
+    unconstrained.extend_from_slice(&[0; 1024]);
+    let (_, tree) = extract(&unconstrained);
+    assert_ne!(tree, original);
+  }
+
+  fn scan(archive: &[u8]) -> Vec<Frame> {
+    MapStrategy::new(archive).scan_sorted(&Params::default(), None).unwrap()
+  }
+
+  fn has_gnu_tar() -> bool {
+    let output = Command::new("tar").arg("--version").output();
+    output.is_ok_and(|output| output.stdout.starts_with(b"tar (GNU tar)"))
+  }
+
+  // Extracts the archive with GNU tar, returning whether it succeeded and a
+  // description of the resulting tree.
+  fn extract(archive: &[u8]) -> (bool, Vec<String>) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut child = Command::new("tar")
+      .args(["-x", "-f", "-", "-C"])
+      .arg(dir.path())
+      .stdin(Stdio::piped())
+      .stderr(Stdio::null())
+      .spawn()
+      .unwrap();
+    // tar may stop reading early, on errors or at the end-of-archive marker.
+    let _ = child.stdin.take().unwrap().write_all(archive);
+    let ok = child.wait().unwrap().success();
+    let mut tree = Vec::new();
+    describe(dir.path(), Path::new(""), &mut HashMap::new(), &mut tree).unwrap();
+    (ok, tree)
+  }
+
+  // Describes each path under root/relative: its type, mode and modification
+  // time, and its content, link target or the path it is a hard link to.
+  fn describe(
+    root: &Path,
+    relative: &Path,
+    inodes: &mut HashMap<u64, String>,
+    tree: &mut Vec<String>,
+  ) -> io::Result<()> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(root.join(relative))? {
+      names.push(entry?.file_name());
+    }
+    names.sort();
+    for name in names {
+      let path = relative.join(name);
+      let full_path = root.join(&path);
+      let metadata = fs::symlink_metadata(&full_path)?;
+      let display = path.display().to_string();
+      let detail = if metadata.is_dir() {
+        "directory".to_string()
+      } else if metadata.is_symlink() {
+        format!("symlink to {}", fs::read_link(&full_path)?.display())
+      } else if let Some(first) = inodes.get(&metadata.ino()) {
+        format!("hard link to {first}")
+      } else {
+        inodes.insert(metadata.ino(), display.clone());
+        format!("file {:x}", xxhash_rust::xxh3::xxh3_64(&fs::read(&full_path)?))
+      };
+      tree.push(format!("{display}: {detail}, mode {:o}, mtime {}", metadata.mode() & 0o7777, metadata.mtime()));
+      if metadata.is_dir() {
+        describe(root, &path, inodes, tree)?;
+      }
+    }
+    Ok(())
+  }
+}
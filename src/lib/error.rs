@@ -0,0 +1,147 @@
+// The error type shared by all of tarcrush's operations.
+//
+// Where an error has an underlying cause (an I/O error, or a field that
+// failed to parse), that is exposed as its source rather than included in its
+// message, so callers can report the whole chain.
+
+use crate::tar::{self, ParseNumericError};
+use crate::verify::Divergence;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+  IngressIO(io::Error),
+  // Failure to read an index given to reuse shingleprints from.
+  IndexIO(io::Error),
+  SpoolIO(io::Error),
+  EgressIO(io::Error),
+  Compression(io::Error),
+  MalformedInput(Malformed),
+  // As MalformedInput, but in the crushed archive when verifying one against
+  // its original.
+  MalformedCrushed(Malformed),
+  // The offset is that of the byte within the index at which the problem was
+  // detected.
+  MalformedIndex(usize, &'static str),
+  // The index was built with different params, so its shingleprints can't
+  // be compared with those of a new scan.
+  IncompatibleIndex,
+  // A crushed archive isn't faithful to the original it was checked against.
+  Divergence(Divergence),
+  CompanionThreadDied,
+}
+
+// Where and how an archive is malformed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Malformed {
+  // The offset of the byte within the archive at which the problem was
+  // detected.
+  pub offset: usize,
+  // The real name of the member at fault, if it can be told.
+  pub member: Option<Vec<u8>>,
+  pub kind: Malformation,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Malformation {
+  // The archive ended partway through a header block or a member's content.
+  PrematureEof,
+  // A numeric header field (identified by name) could not be parsed.
+  BadNumericField(&'static str, ParseNumericError),
+  // A member's content length (given) is too large to be addressed.
+  OversizedMember(u64),
+  // A pax extended header could not be parsed, for the reason given.
+  BadPaxRecord(&'static str),
+  // A crushed archive's restore data is missing or unusable, for the reason
+  // given.
+  BadRestoreData(&'static str),
+}
+
+impl Error {
+  pub(crate) fn malformed(offset: usize, kind: Malformation) -> Self {
+    Error::MalformedInput(Malformed { offset, member: None, kind })
+  }
+
+  // Attributes a malformation to the member that the given frame (or the
+  // start of one) is for, unless it has already been attributed to one.
+  pub(crate) fn in_member(mut self, frame: &[u8]) -> Self {
+    if let Error::MalformedInput(Malformed { member: member @ None, .. }) = &mut self {
+      *member = tar::member_name(frame);
+    }
+    self
+  }
+}
+
+impl fmt::Display for Malformation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Malformation::PrematureEof => write!(f, "premature EOF"),
+      Malformation::BadNumericField(field, _) => write!(f, "malformed {field} field"),
+      Malformation::OversizedMember(len) => write!(f, "member too large ({len} bytes)"),
+      Malformation::BadPaxRecord(msg) => write!(f, "malformed pax extended header: {msg}"),
+      Malformation::BadRestoreData(msg) => f.write_str(msg),
+    }
+  }
+}
+
+impl fmt::Display for Malformed {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "at byte {}", self.offset)?;
+    if let Some(member) = &self.member {
+      write!(f, " (member \"{}\")", String::from_utf8_lossy(member))?;
+    }
+    write!(f, ": {}", self.kind)
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::IngressIO(_) => write!(f, "failed to read input"),
+      Error::IndexIO(_) => write!(f, "failed to read index"),
+      Error::SpoolIO(_) => write!(f, "failed to spool input to a temporary file"),
+      Error::EgressIO(_) => write!(f, "failed to write output"),
+      Error::Compression(_) => write!(f, "failed to compress"),
+      Error::MalformedInput(malformed) => write!(f, "malformed input {malformed}"),
+      Error::MalformedCrushed(malformed) => write!(f, "malformed crushed archive {malformed}"),
+      Error::MalformedIndex(offset, msg) => write!(f, "malformed index at byte {offset}: {msg}"),
+      Error::IncompatibleIndex => {
+        write!(f, "index was built with a different shingle length, feature count or target")
+      }
+      Error::Divergence(divergence) => divergence.fmt(f),
+      Error::CompanionThreadDied => write!(f, "a worker thread exited unexpectedly"),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::IngressIO(err) | Error::IndexIO(err) | Error::SpoolIO(err) | Error::EgressIO(err) | Error::Compression(err) => Some(err),
+      Error::MalformedInput(malformed) | Error::MalformedCrushed(malformed) => match &malformed.kind {
+        Malformation::BadNumericField(_, err) => Some(err),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::testing::member;
+  use std::error::Error as _;
+
+  #[test]
+  fn test_display_and_source() {
+    let err = Error::malformed(127, Malformation::BadNumericField("size", ParseNumericError(3, "invalid octal digit")));
+    let err = err.in_member(&member(b"a.txt", b'0', b"")).in_member(&member(b"b.txt", b'0', b""));
+    assert_eq!(err.to_string(), "malformed input at byte 127 (member \"a.txt\"): malformed size field");
+    assert_eq!(err.source().unwrap().to_string(), "invalid octal digit at byte 3 of field");
+    let err = Error::EgressIO(io::Error::other("disk full"));
+    assert_eq!(err.to_string(), "failed to write output");
+    assert_eq!(err.source().unwrap().to_string(), "disk full");
+  }
+}
@@ -0,0 +1,427 @@
+// Frame indexes, which record the outcome of scanning an archive so that
+// later scans of the same or a similar archive can skip shingleprinting
+// frames that haven't changed.
+//
+// A frame's shingleprints depend only on the bytes of its head and tail (or
+// of its whole content, if that's no longer than the head and tail length),
+// so each entry is keyed by a digest of exactly those bytes. Frames are
+// matched by digest alone, wherever they are in the archive.
+//
+// The binary format is laid out as follows, with all integers little-endian:
+//   - INDEX_MAGIC
+//   - u64 format version (INDEX_VERSION)
+//   - u64 shingle length, u64 feature count, u64 head and tail length
+//   - u64 number of entries
+//   - for each entry, in archive order:
+//     - u64 offset in the archive, u64 length, u64 digest
+//     - u64 length of the member name, followed by the name
+//     - for the head and then the tail shingleprint: u8 number of hashes,
+//       followed by each u32 hash. The tail is omitted if the length is no
+//       more than the head and tail length, since it's the same as the head.
+//   - u64 XXH3 checksum of all of the above
+
+use crate::ingress::{self, Strategy};
+use crate::params::Params;
+use crate::shingleprint::{Shingleprint, ShingleprintConfig};
+use crate::tar;
+use crate::Error;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::ops::Range;
+use xxhash_rust::xxh3;
+
+const INDEX_MAGIC: &[u8; 8] = b"TCINDEX\0";
+const INDEX_VERSION: u64 = 1;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Index {
+  pub shingleprint: ShingleprintConfig,
+  pub head_and_tail_len: usize,
+  // In archive order.
+  pub entries: Vec<Entry>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+  pub bounds: Range<usize>,
+  pub digest: u64,
+  pub name: Vec<u8>,
+  pub head_sp: Shingleprint,
+  pub tail_sp: Shingleprint,
+}
+
+impl Index {
+  // Whether the index's shingleprints are those a scan with params would produce.
+  pub fn is_compatible(&self, params: &Params) -> bool {
+    self.shingleprint == params.shingleprint && self.head_and_tail_len == params.head_and_tail_len
+  }
+}
+
+// Digest of the bytes a frame's shingleprints are computed from: its head and
+// tail, or, if it has no separate tail, its whole content given as head.
+pub(crate) fn frame_digest(head: &[u8], tail: Option<&[u8]>) -> u64 {
+  let mut hasher = xxh3::Xxh3::new();
+  hasher.update(head);
+  if let Some(tail) = tail {
+    hasher.update(tail);
+  }
+  hasher.digest()
+}
+
+// Shingleprints from an index, looked up by the digest of the bytes they
+// were computed from.
+#[derive(Debug)]
+pub struct Cache {
+  prints: HashMap<u64, (Shingleprint, Shingleprint)>,
+}
+
+impl Cache {
+  pub fn new(index: &Index, params: &Params) -> Result<Self, Error> {
+    if !index.is_compatible(params) {
+      return Err(Error::IncompatibleIndex);
+    }
+    let prints = index
+      .entries
+      .iter()
+      .map(|entry| (entry.digest, (entry.head_sp.clone(), entry.tail_sp.clone())))
+      .collect();
+    Ok(Self { prints })
+  }
+
+  pub(crate) fn get(&self, head: &[u8], tail: Option<&[u8]>) -> Option<&(Shingleprint, Shingleprint)> {
+    self.prints.get(&frame_digest(head, tail))
+  }
+}
+
+// Scans the archive provided by the ingress strategy, reusing shingleprints
+// from the cache where possible, and indexes its frames.
+pub fn build_index(
+  strategy: &mut (dyn Strategy + Send),
+  params: &Params,
+  cache: Option<&Cache>,
+) -> Result<Index, Error> {
+  let frames = strategy.scan_sorted(params, cache)?;
+  let content = strategy.content();
+  let entries = frames
+    .into_iter()
+    .map(|frame| {
+      let frame_content = &content[frame.bounds.clone()];
+      let (head, tail) = ingress::head_and_tail(frame_content, params.head_and_tail_len);
+      Entry {
+        digest: frame_digest(head, tail),
+        // Frames have already been validated by scanning, but anything
+        // unexpected just results in an empty name.
+        name: tar::Member::parse(frame_content).map(|member| member.name).unwrap_or_default(),
+        bounds: frame.bounds,
+        head_sp: frame.head_sp,
+        tail_sp: frame.tail_sp,
+      }
+    })
+    .collect();
+  Ok(Index { shingleprint: params.shingleprint, head_and_tail_len: params.head_and_tail_len, entries })
+}
+
+pub fn write_index(index: &Index, out: &mut dyn Write) -> io::Result<()> {
+  let mut data = Vec::new();
+  data.extend_from_slice(INDEX_MAGIC);
+  for field in [
+    INDEX_VERSION,
+    index.shingleprint.shingle_len() as u64,
+    index.shingleprint.features() as u64,
+    index.head_and_tail_len as u64,
+    index.entries.len() as u64,
+  ] {
+    data.extend_from_slice(&field.to_le_bytes());
+  }
+  for entry in &index.entries {
+    for field in [entry.bounds.start as u64, entry.bounds.len() as u64, entry.digest, entry.name.len() as u64] {
+      data.extend_from_slice(&field.to_le_bytes());
+    }
+    data.extend_from_slice(&entry.name);
+    let prints = if entry.bounds.len() <= index.head_and_tail_len {
+      debug_assert_eq!(entry.head_sp, entry.tail_sp);
+      &[&entry.head_sp][..]
+    } else {
+      &[&entry.head_sp, &entry.tail_sp]
+    };
+    for sp in prints {
+      data.push(sp.hashes().len() as u8);
+      for hash in sp.hashes() {
+        data.extend_from_slice(&hash.to_le_bytes());
+      }
+    }
+  }
+  let checksum = xxh3::xxh3_64(&data);
+  data.extend_from_slice(&checksum.to_le_bytes());
+  out.write_all(&data)?;
+  out.flush()
+}
+
+// Writes the index as JSON, for inspection by other tools. Digests are given
+// as hexadecimal strings, since JSON numbers can't generally hold 64 bits.
+// Names that aren't valid UTF-8 have invalid sequences replaced.
+pub fn write_index_json(index: &Index, out: &mut dyn Write) -> io::Result<()> {
+  writeln!(out, "{{")?;
+  writeln!(out, "  \"version\": {INDEX_VERSION},")?;
+  writeln!(out, "  \"shingle_len\": {},", index.shingleprint.shingle_len())?;
+  writeln!(out, "  \"features\": {},", index.shingleprint.features())?;
+  writeln!(out, "  \"head_and_tail_len\": {},", index.head_and_tail_len)?;
+  write!(out, "  \"frames\": [")?;
+  for (i, entry) in index.entries.iter().enumerate() {
+    let separator = if i == 0 { "" } else { "," };
+    let hashes = |sp: &Shingleprint| sp.hashes().iter().map(u32::to_string).collect::<Vec<_>>().join(", ");
+    write!(
+      out,
+      "{separator}\n    {{\"offset\": {}, \"len\": {}, \"digest\": \"{:016x}\", \"name\": \"{}\", \"head\": [{}], \"tail\": [{}]}}",
+      entry.bounds.start,
+      entry.bounds.len(),
+      entry.digest,
+      json_escape(&String::from_utf8_lossy(&entry.name)),
+      hashes(&entry.head_sp),
+      hashes(&entry.tail_sp),
+    )?;
+  }
+  if !index.entries.is_empty() {
+    write!(out, "\n  ")?;
+  }
+  writeln!(out, "]")?;
+  writeln!(out, "}}")?;
+  out.flush()
+}
+
+fn json_escape(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      '\t' => escaped.push_str("\\t"),
+      c if c < ' ' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+// Reads input sequentially, reporting truncation at its end.
+struct Reader<'a> {
+  input: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+    let bytes = self
+      .input
+      .get(self.offset..self.offset.saturating_add(len))
+      .ok_or(Error::MalformedIndex(self.input.len(), "truncated index"))?;
+    self.offset += len;
+    Ok(bytes)
+  }
+
+  fn u64(&mut self) -> Result<u64, Error> {
+    Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+  }
+
+  fn usize(&mut self) -> Result<usize, Error> {
+    let offset = self.offset;
+    usize::try_from(self.u64()?).map_err(|_| Error::MalformedIndex(offset, "index field out of range"))
+  }
+
+  fn shingleprint(&mut self, config: &ShingleprintConfig) -> Result<Shingleprint, Error> {
+    let offset = self.offset;
+    let len = self.bytes(1)?[0] as usize;
+    let hashes: Vec<u32> =
+      self.bytes(len * 4)?.chunks_exact(4).map(|hash| u32::from_le_bytes(hash.try_into().unwrap())).collect();
+    Shingleprint::from_hashes(&hashes, config).ok_or(Error::MalformedIndex(offset, "malformed shingleprint"))
+  }
+}
+
+pub fn read_index(input: &[u8]) -> Result<Index, Error> {
+  if !input.starts_with(INDEX_MAGIC) {
+    return Err(Error::MalformedIndex(0, "not a tarcrush index"));
+  }
+  let Some(body_len) = input.len().checked_sub(8).filter(|&len| len >= INDEX_MAGIC.len()) else {
+    return Err(Error::MalformedIndex(input.len(), "truncated index"));
+  };
+  let checksum = u64::from_le_bytes(input[body_len..].try_into().unwrap());
+  if xxh3::xxh3_64(&input[..body_len]) != checksum {
+    return Err(Error::MalformedIndex(body_len, "checksum mismatch"));
+  }
+  let mut reader = Reader { input: &input[..body_len], offset: INDEX_MAGIC.len() };
+  if reader.u64()? != INDEX_VERSION {
+    return Err(Error::MalformedIndex(INDEX_MAGIC.len(), "unsupported index version"));
+  }
+  let config_offset = reader.offset;
+  let shingleprint = ShingleprintConfig::new(reader.usize()?, reader.usize()?)
+    .map_err(|_| Error::MalformedIndex(config_offset, "unsupported shingleprint config"))?;
+  let head_and_tail_len = reader.usize()?;
+  let n_entries = reader.usize()?;
+  // Each entry takes at least 33 bytes, so a bogus count can't cause a huge allocation.
+  let mut entries = Vec::with_capacity(n_entries.min(body_len / 33));
+  for _ in 0..n_entries {
+    let entry_offset = reader.offset;
+    let start = reader.usize()?;
+    let len = reader.usize()?;
+    let digest = reader.u64()?;
+    let name_len = reader.usize()?;
+    let name = reader.bytes(name_len)?.to_vec();
+    let head_sp = reader.shingleprint(&shingleprint)?;
+    let tail_sp = if len <= head_and_tail_len { head_sp.clone() } else { reader.shingleprint(&shingleprint)? };
+    let bounds = start..start.checked_add(len).ok_or(Error::MalformedIndex(entry_offset, "entry out of range"))?;
+    if entries.last().is_some_and(|prev: &Entry| prev.bounds.end > bounds.start) {
+      return Err(Error::MalformedIndex(entry_offset, "entries overlap"));
+    }
+    entries.push(Entry { bounds, digest, name, head_sp, tail_sp });
+  }
+  if reader.offset != body_len {
+    return Err(Error::MalformedIndex(reader.offset, "trailing data after entries"));
+  }
+  Ok(Index { shingleprint, head_and_tail_len, entries })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ingress::{MapStrategy, ReadStrategy};
+  use crate::shingleprint::shingleprint;
+  use crate::util::testing::{member, random_bytes, END_OF_ARCHIVE};
+  use crate::Frame;
+
+  fn archive() -> Vec<u8> {
+    [
+      member(b"a.txt", b'0', b"The quick brown fox jumps over the lazy dog."),
+      member(b"b.bin", b'0', &random_bytes(1, 100_000)),
+      member(b"././@LongLink", b'L', b"x/long/name\0"),
+      member(b"x/long/nam", b'0', b"\"quoted\"\n"),
+      END_OF_ARCHIVE.to_vec(),
+    ]
+    .concat()
+  }
+
+  fn index(archive: &[u8], params: &Params, cache: Option<&Cache>) -> Index {
+    build_index(&mut MapStrategy::new(archive), params, cache).unwrap()
+  }
+
+  fn scan(archive: &[u8], params: &Params, cache: Option<&Cache>) -> Vec<Frame> {
+    scan_with(&mut MapStrategy::new(archive), params, cache)
+  }
+
+  fn scan_with(strategy: &mut dyn Strategy, params: &Params, cache: Option<&Cache>) -> Vec<Frame> {
+    strategy.scan_sorted(params, cache).unwrap()
+  }
+
+  #[test]
+  fn test_build_index() {
+    let archive = archive();
+    let params = Params::default();
+    let index = index(&archive, &params, None);
+    let names: Vec<_> = index.entries.iter().map(|entry| entry.name.as_slice()).collect();
+    assert_eq!(names, [&b"a.txt"[..], b"b.bin", b"x/long/name"]);
+    let bounds: Vec<_> = index.entries.iter().map(|entry| entry.bounds.clone()).collect();
+    assert_eq!(bounds, [0..1024, 1024..101888, 101888..103936]);
+    let frames = scan(&archive, &params, None);
+    for (entry, frame) in index.entries.iter().zip(&frames) {
+      assert_eq!(entry.head_sp, frame.head_sp);
+      assert_eq!(entry.tail_sp, frame.tail_sp);
+    }
+    let big = &archive[1024..101888];
+    let h = params.head_and_tail_len;
+    assert_eq!(index.entries[1].digest, frame_digest(&big[..h], Some(&big[big.len() - h..])));
+    assert_eq!(index.entries[0].digest, frame_digest(&archive[..1024], None));
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let config = ShingleprintConfig::new(12, 40).unwrap();
+    let params = Params { shingleprint: config, ..Params::default() };
+    let index = index(&archive(), &params, None);
+    let mut data = Vec::new();
+    write_index(&index, &mut data).unwrap();
+    assert_eq!(read_index(&data).unwrap(), index);
+
+    let empty = Index { shingleprint: config, head_and_tail_len: 4096, entries: Vec::new() };
+    let mut data = Vec::new();
+    write_index(&empty, &mut data).unwrap();
+    assert_eq!(read_index(&data).unwrap(), empty);
+  }
+
+  #[test]
+  fn test_corrupt_index() {
+    let mut data = Vec::new();
+    write_index(&index(&archive(), &Params::default(), None), &mut data).unwrap();
+    assert!(matches!(read_index(b""), Err(Error::MalformedIndex(0, _))));
+    assert!(matches!(read_index(&data[..data.len() - 1]), Err(Error::MalformedIndex(..))));
+    for offset in [0, 8, 50, data.len() / 2, data.len() - 1] {
+      let mut corrupt = data.clone();
+      corrupt[offset] ^= 1;
+      assert!(matches!(read_index(&corrupt), Err(Error::MalformedIndex(..))), "offset {offset}");
+    }
+    // A well-formed checksum doesn't excuse a malformed body.
+    let mut corrupt = data[..data.len() - 8].to_vec();
+    corrupt[16] = 0; // shingle length
+    corrupt.extend_from_slice(&xxh3::xxh3_64(&corrupt).to_le_bytes());
+    assert!(matches!(read_index(&corrupt), Err(Error::MalformedIndex(16, _))));
+  }
+
+  #[test]
+  fn test_json() {
+    let index = index(&archive(), &Params::default(), None);
+    let mut json = Vec::new();
+    write_index_json(&index, &mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with("{\n  \"version\": 1,\n  \"shingle_len\": 16,"), "{json}");
+    assert!(json.contains("\"offset\": 1024, \"len\": 100864,"), "{json}");
+    assert!(json.contains("\"name\": \"x/long/name\""), "{json}");
+    assert_eq!(json_escape("\"a\\b\"\n\u{1}"), "\\\"a\\\\b\\\"\\n\\u0001");
+  }
+
+  #[test]
+  fn test_cache_reuses_prints() {
+    let params = Params::default();
+    let old = archive();
+    let mut index = index(&old, &params, None);
+    // Append a member, and change another, so that only some frames match.
+    let new = [
+      &member(b"a.txt", b'0', b"The quick brown fox jumps over the lazy cat."),
+      &old[1024..old.len() - 1024],
+      &member(b"c.txt", b'0', b"something new"),
+      &END_OF_ARCHIVE,
+    ]
+    .concat();
+    let expected = scan(&new, &params, None);
+    let cache = Cache::new(&index, &params).unwrap();
+    let got = scan(&new, &params, Some(&cache));
+    assert_eq!(got.len(), expected.len());
+    for (got, expected) in got.iter().zip(&expected) {
+      assert_eq!((&got.bounds, &got.head_sp, &got.tail_sp), (&expected.bounds, &expected.head_sp, &expected.tail_sp));
+    }
+
+    // Prints found in the cache are used as they are, not recomputed.
+    let bogus = shingleprint(b"something else entirely", &params.shingleprint);
+    // Those of the changed member are not found.
+    index.entries[0].head_sp = bogus.clone();
+    index.entries[1].head_sp = bogus.clone();
+    let cache = Cache::new(&index, &params).unwrap();
+    let got = scan(&new, &params, Some(&cache));
+    assert_eq!(got[0].head_sp, expected[0].head_sp);
+    assert_eq!(got[1].head_sp, bogus);
+    assert_eq!(got[3].head_sp, expected[3].head_sp);
+    let got = scan_with(&mut ReadStrategy::new(&new[..]), &params, Some(&cache));
+    assert_eq!(got[0].head_sp, expected[0].head_sp);
+    assert_eq!(got[1].head_sp, bogus);
+    assert_eq!(got[1].tail_sp, expected[1].tail_sp);
+    assert_eq!(got[3].head_sp, expected[3].head_sp);
+  }
+
+  #[test]
+  fn test_incompatible_cache() {
+    let index = index(&archive(), &Params::default(), None);
+    let params = Params::for_target(crate::params::Target::Xz);
+    assert!(matches!(Cache::new(&index, &params), Err(Error::IncompatibleIndex)));
+    let config = ShingleprintConfig::new(8, 32).unwrap();
+    let params = Params { shingleprint: config, ..Params::default() };
+    assert!(matches!(Cache::new(&index, &params), Err(Error::IncompatibleIndex)));
+  }
+}
@@ -0,0 +1,366 @@
+use crate::index::Cache;
+use crate::error::{Error, Malformation};
+use crate::ingress::{frame_prints, head_and_tail, PaxSizes, Strategy};
+use crate::params::Params;
+use crate::shingleprint::ShingleprintConfig;
+use crate::tar;
+use crate::Frame;
+use crossbeam::channel::{self, Receiver, Sender};
+use std::ops::Range;
+use std::fmt;
+
+pub struct MapStrategy<'m> {
+  archive_content: &'m [u8],
+}
+
+impl<'m> MapStrategy<'m> {
+  pub fn new(archive_content: &'m [u8]) -> Self {
+    Self { archive_content }
+  }
+}
+
+impl<'m> fmt::Debug for MapStrategy<'m> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("MapStrategy").finish_non_exhaustive()
+  }
+}
+
+impl<'m> Strategy for MapStrategy<'m> {
+  fn scan_with_cache(&mut self, params: &Params, cache: Option<&Cache>, frames_out: Sender<Frame>) -> Result<(), Error> {
+    std::thread::scope(|scope| {
+      let archive_content = self.archive_content;
+      let head_and_tail_len = params.head_and_tail_len;
+      let config = params.shingleprint;
+      let (ranges_out, ranges_in) = channel::bounded(64);
+      let shingleprinting_threads: Vec<_> = (0..crate::tunables::N_SHINGLEPRINTING_THREADS)
+        .map(|_| {
+          let ranges_in = ranges_in.clone();
+          let frames_out = frames_out.clone();
+          scope.spawn(move || Self::shingleprint_frames(ranges_in, frames_out, archive_content, head_and_tail_len, config, cache))
+        })
+        .collect();
+      drop(ranges_in);
+      let result = Self::split_frames(archive_content, ranges_out);
+      for thread in shingleprinting_threads {
+        thread.join().expect("shingleprinting thread panicked");
+      }
+      result
+    })
+  }
+
+  fn content(&self) -> &[u8] {
+    self.archive_content
+  }
+}
+
+impl<'m> MapStrategy<'m> {
+  // The bounds of each frame, without shingleprinting them.
+  pub fn frame_bounds(&self) -> Result<Vec<Range<usize>>, Error> {
+    let (ranges_out, ranges_in) = channel::unbounded();
+    Self::split_frames(self.archive_content, ranges_out)?;
+    Ok(ranges_in.into_iter().map(|(bounds, _)| bounds).collect())
+  }
+
+  // Sends the bounds of each frame, and whether it's a barrier. The frames
+  // cover the archive up to its end-of-archive marker, if any.
+  fn split_frames(archive_content: &'m [u8], ranges_out: Sender<(Range<usize>, bool)>) -> Result<(), Error> {
+    let premature_eof = || Error::malformed(archive_content.len(), Malformation::PrematureEof);
+    let mut frame_offset = 0;
+    let mut header_offset = 0;
+    let mut pax_sizes = PaxSizes::default();
+    while header_offset < archive_content.len() {
+      let header: &[u8] = match archive_content.get(header_offset..header_offset + 512) {
+        Some(x) => x,
+        None => return Err(premature_eof()),
+      };
+      let header: &[u8; 512] = header.try_into().unwrap();
+      let header = tar::Header(header);
+      if header.is_zero() && frame_offset == header_offset {
+        // Tar implementations stop reading at the first zero block where a
+        // header is expected, so whatever follows isn't split into frames.
+        break;
+      }
+      let in_member = |err: Error| err.in_member(&archive_content[frame_offset..]);
+      let (content_len, padded_content_len) = pax_sizes.content_len(header, header_offset).map_err(in_member)?;
+      let mut content_offset = header_offset + 512;
+      // A GNU sparse member's map may continue in extension blocks between
+      // its header and its content.
+      let mut extended = header.is_extended();
+      while extended {
+        let block: &[u8] = match archive_content.get(content_offset..content_offset + 512) {
+          Some(x) => x,
+          None => return Err(in_member(premature_eof())),
+        };
+        extended = tar::sparse::SparseExtension(block.try_into().unwrap()).is_extended();
+        content_offset += 512;
+      }
+      header_offset = match content_offset.checked_add(padded_content_len) {
+        Some(end) if end <= archive_content.len() => end,
+        Some(_) => return Err(in_member(premature_eof())),
+        None => {
+          let err = Error::malformed(header_offset + 124, Malformation::OversizedMember(content_len));
+          return Err(in_member(err));
+        }
+      };
+      let content = &archive_content[content_offset..content_offset + content_len as usize];
+      pax_sizes.update(header, content, content_offset).map_err(in_member)?;
+      if !header.is_prefix() {
+        ranges_out
+          .send((frame_offset..header_offset, header.is_global()))
+          .expect("channel disconnected");
+        frame_offset = header_offset;
+      }
+    }
+    if frame_offset != header_offset {
+      // The archive ended with prefix records that don't apply to any member.
+      return Err(premature_eof());
+    }
+    Ok(())
+  }
+  fn shingleprint_frames(
+    ranges_in: Receiver<(Range<usize>, bool)>,
+    frames_out: Sender<Frame>,
+    archive_content: &'m [u8],
+    head_and_tail_len: usize,
+    config: ShingleprintConfig,
+    cache: Option<&Cache>,
+  ) {
+    while let Ok((bounds, barrier)) = ranges_in.recv() {
+      let (head, tail) = head_and_tail(&archive_content[bounds.clone()], head_and_tail_len);
+      let (head_sp, tail_sp) = frame_prints(head, tail, &config, cache);
+      frames_out.send(Frame { bounds, head_sp, tail_sp, barrier }).expect("channel disconnected");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::error::Malformed;
+  use crate::shingleprint::shingleprint;
+  use crate::tar::ParseNumericError;
+  use crate::util::testing::{member, random_bytes, END_OF_ARCHIVE};
+
+  fn scan(archive: &[u8]) -> Result<Vec<Frame>, Error> {
+    scan_with(archive, &Params::default())
+  }
+
+  fn scan_with(archive: &[u8], params: &Params) -> Result<Vec<Frame>, Error> {
+    MapStrategy::new(archive).scan_sorted(params, None)
+  }
+
+  #[test]
+  fn test_split() {
+    let archive = [
+      member(b"a.txt", b'0', b"hello"),
+      member(b"././@LongLink", b'L', b"some/very/long/name"),
+      member(b"some/very/long/nam", b'0', &[b'x'; 1000]),
+      member(b"empty", b'0', b""),
+      END_OF_ARCHIVE.to_vec(),
+    ]
+    .concat();
+    let bounds: Vec<_> = scan(&archive).unwrap().into_iter().map(|frame| frame.bounds).collect();
+    assert_eq!(bounds, [0..1024, 1024..3584, 3584..4096]);
+  }
+
+  #[test]
+  fn test_shingleprints() {
+    let len = Params::default().head_and_tail_len;
+    let big = member(b"big", b'0', &random_bytes(1, 3 * len));
+    let archive = [member(b"small", b'0', b"hello"), big.clone()].concat();
+    let frames = scan(&archive).unwrap();
+    let config = ShingleprintConfig::default();
+    assert_eq!(frames[0].head_sp, shingleprint(&archive[..1024], &config));
+    assert_eq!(frames[0].tail_sp, frames[0].head_sp);
+    assert_eq!(frames[1].head_sp, shingleprint(&big[..len], &config));
+    assert_eq!(frames[1].tail_sp, shingleprint(&big[big.len() - len..], &config));
+  }
+
+  #[test]
+  fn test_head_and_tail_len() {
+    use crate::params::Target;
+    let big = member(b"big", b'0', &random_bytes(1, 1024 * 1024));
+    for target in Target::ALL {
+      let params = Params::for_target(target);
+      let frames = scan_with(&big, &params).unwrap();
+      let config = &params.shingleprint;
+      assert_eq!(frames[0].head_sp, shingleprint(&big[..params.head_and_tail_len], config));
+      assert_eq!(frames[0].tail_sp, shingleprint(&big[big.len() - params.head_and_tail_len..], config));
+    }
+  }
+
+  #[test]
+  fn test_shingleprint_config() {
+    let big = member(b"big", b'0', &random_bytes(1, 100000));
+    let config = ShingleprintConfig::new(8, 48).unwrap();
+    let params = Params { shingleprint: config, ..Params::default() };
+    let frames = scan_with(&big, &params).unwrap();
+    assert_eq!(frames[0].head_sp, shingleprint(&big[..params.head_and_tail_len], &config));
+    assert_eq!(frames[0].head_sp.hashes().len(), 48);
+  }
+
+  #[test]
+  fn test_pax_headers() {
+    use crate::tar::pax::build_records;
+    use crate::util::testing::header;
+    let padded = |content: &[u8]| [content, &vec![0; content.len().next_multiple_of(512) - content.len()]].concat();
+    let archive = [
+      // The size field is overridden, as it would be for a member too large for it.
+      member(b"PaxHeaders/big", b'x', &build_records(&[(b"path", b"big"), (b"size", b"3000")])),
+      header(b"big", b'0', 0).to_vec(),
+      padded(&random_bytes(1, 3000)),
+      // A global size applies to every later member, unless cancelled.
+      member(b"GlobalHead.0", b'g', &build_records(&[(b"size", b"600")])),
+      header(b"a", b'0', 0).to_vec(),
+      padded(&[b'a'; 600]),
+      member(b"PaxHeaders/b", b'x', &build_records(&[(b"size", b"")])),
+      member(b"b", b'0', b"hello"),
+      member(b"././@LongLink", b'L', b"some/very/long/name"),
+      header(b"c", b'0', 0).to_vec(),
+      padded(&[b'c'; 600]),
+      member(b"GlobalHead.1", b'g', &build_records(&[(b"size", b"")])),
+      member(b"d", b'0', b"world"),
+      END_OF_ARCHIVE.to_vec(),
+    ]
+    .concat();
+    let frames = scan(&archive).unwrap();
+    let bounds: Vec<_> = frames.iter().map(|frame| frame.bounds.clone()).collect();
+    assert_eq!(bounds, [0..4608, 4608..5632, 5632..7168, 7168..9216, 9216..11776, 11776..12800, 12800..13824]);
+    let barriers: Vec<_> = frames.iter().map(|frame| frame.barrier).collect();
+    assert_eq!(barriers, [false, true, false, false, false, true, false]);
+  }
+
+  #[test]
+  fn test_gnu_sparse() {
+    use crate::util::testing::gnu_sparse_member;
+    let pairs: Vec<_> = (0..50).map(|i| (1024 * i, 512)).collect();
+    // 50 entries take three extension blocks.
+    let sparse = gnu_sparse_member(b"sparse", &pairs, 1024 * 50);
+    assert_eq!(sparse.len(), 512 + 3 * 512 + 50 * 512);
+    let small = gnu_sparse_member(b"small", &pairs[..3], 1024 * 3);
+    let archive = [small, sparse, member(b"a", b'0', b"hello"), END_OF_ARCHIVE.to_vec()].concat();
+    let bounds: Vec<_> = scan(&archive).unwrap().into_iter().map(|frame| frame.bounds).collect();
+    assert_eq!(bounds, [0..2048, 2048..29696, 29696..30720]);
+    let member = tar::Member::parse(&archive[2048..29696]).unwrap();
+    assert_eq!(member.data_offset, 2048);
+    assert_eq!(member.sparse.unwrap().entries.len(), 50);
+    assert_eq!(scan_err(&archive[..2048 + 800]), (2048 + 800, Malformation::PrematureEof));
+  }
+
+  #[test]
+  fn test_bad_pax_header() {
+    use crate::tar::pax::build_records;
+    let archive = [member(b"PaxHeaders/a", b'x', b"9 size=x\n"), member(b"a", b'0', b"")].concat();
+    assert_eq!(scan_err(&archive), (512, Malformation::BadPaxRecord("bad size")));
+    assert_eq!(scan_malformed(&archive).member.as_deref(), Some(&b"a"[..]));
+    // Names from the member's extension records are used, where they can be read.
+    let records = build_records(&[(b"path", b"pax/a")]);
+    let archive = [
+      member(b"././@LongLink", b'L', b"long/a\0"),
+      member(b"PaxHeaders/a", b'x', &[&records[..], b"9 size=x\n"].concat()),
+      member(b"a", b'0', b""),
+    ]
+    .concat();
+    assert_eq!(scan_malformed(&archive).member.as_deref(), Some(&b"pax/a"[..]));
+    // Other bad values don't matter for splitting.
+    let archive = [member(b"PaxHeaders/a", b'x', b"11 mtime=x\n"), member(b"a", b'0', b"")].concat();
+    assert_eq!(scan(&archive).unwrap().len(), 1);
+  }
+
+  #[test]
+  fn test_empty() {
+    assert!(scan(b"").unwrap().is_empty());
+    assert!(scan(&END_OF_ARCHIVE).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_end_of_archive() {
+    // Nothing after the first zero block is split, not even another member
+    // or a partial block.
+    let a = member(b"a.txt", b'0', b"hello");
+    let archive = [&a[..], &[0; 512], &member(b"b.txt", b'0', b"world"), &[0; 1024], b"garbage"].concat();
+    let frames = scan(&archive).unwrap();
+    assert_eq!((frames.len(), frames[0].bounds.clone()), (1, 0..a.len()));
+    assert!(scan(&[&END_OF_ARCHIVE[..], &a].concat()).unwrap().is_empty());
+  }
+
+  // Hand-crafted corrupt archives.
+
+  fn fixture() -> Vec<u8> {
+    [
+      member(b"a.txt", b'0', b"hello"),
+      member(b"b.bin", b'0', &[b'x'; 1000]),
+      END_OF_ARCHIVE.to_vec(),
+    ]
+    .concat()
+  }
+
+  fn scan_malformed(archive: &[u8]) -> Malformed {
+    match scan(archive) {
+      Err(Error::MalformedInput(malformed)) => malformed,
+      other => panic!("expected MalformedInput, got {other:?}"),
+    }
+  }
+
+  fn scan_err(archive: &[u8]) -> (usize, Malformation) {
+    let malformed = scan_malformed(archive);
+    (malformed.offset, malformed.kind)
+  }
+
+  #[test]
+  fn test_truncated_header() {
+    let archive = fixture();
+    assert_eq!(scan_err(&archive[..100]), (100, Malformation::PrematureEof));
+    assert_eq!(scan_err(&archive[..1100]), (1100, Malformation::PrematureEof));
+    assert_eq!(scan_malformed(&archive[..1100]).member, None);
+  }
+
+  #[test]
+  fn test_truncated_content() {
+    let archive = fixture();
+    assert_eq!(scan_err(&archive[..1024 + 1000]), (2024, Malformation::PrematureEof));
+    assert_eq!(scan_malformed(&archive[..1024 + 1000]).member.as_deref(), Some(&b"b.bin"[..]));
+    // Missing only the padding at the end of the content.
+    assert_eq!(scan_err(&archive[..1024 + 1536 - 1]), (2559, Malformation::PrematureEof));
+  }
+
+  #[test]
+  fn test_truncated_after_prefix() {
+    let archive = member(b"././@LongLink", b'L', b"some/very/long/name");
+    assert_eq!(scan_err(&archive), (1024, Malformation::PrematureEof));
+  }
+
+  #[test]
+  fn test_bad_size_field() {
+    let mut archive = fixture();
+    archive[1024 + 124..1024 + 136].copy_from_slice(b"0000000l750\0");
+    let expected = Malformed {
+      offset: 1155,
+      member: Some(b"b.bin".to_vec()),
+      kind: Malformation::BadNumericField("size", ParseNumericError(7, "invalid octal digit")),
+    };
+    assert_eq!(scan_malformed(&archive), expected);
+  }
+
+  #[test]
+  fn test_packed_size_overflow() {
+    let mut archive = fixture();
+    archive[124..136].copy_from_slice(&[0x80, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let kind = Malformation::BadNumericField("size", ParseNumericError(3, "value too large"));
+    assert_eq!(scan_err(&archive), (127, kind));
+  }
+
+  #[test]
+  fn test_oversized_member() {
+    let mut archive = fixture();
+    archive[124..136].copy_from_slice(&[0x80, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(scan_err(&archive), (124, Malformation::OversizedMember(u64::MAX)));
+  }
+
+  #[test]
+  fn test_size_beyond_end() {
+    let mut archive = fixture();
+    archive[124..136].copy_from_slice(b"77777777777\0");
+    assert_eq!(scan_err(&archive), (archive.len(), Malformation::PrematureEof));
+  }
+}
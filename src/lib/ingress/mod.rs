@@ -0,0 +1,329 @@
+use crate::compress::{self, Decoder};
+use crate::error::{Error, Malformation};
+use crate::index::Cache;
+use crate::params::Params;
+use crate::shingleprint::{shingleprint, Shingleprint, ShingleprintConfig};
+use crate::tar;
+use crate::Frame;
+use crossbeam::channel::{self, Sender};
+use memmap::{Mmap, MmapOptions};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd};
+use std::path::Path;
+
+mod map;
+mod read;
+
+pub use map::MapStrategy;
+pub use read::ReadStrategy;
+
+pub fn from_stdin<T, E>(
+  callback: impl for<'a> FnOnce(&'a mut (dyn Strategy + Send)) -> Result<T, E>,
+) -> Result<T, E>
+where
+  E: From<Error>,
+{
+  from_fd(std::io::stdin().lock().as_fd(), callback)
+}
+
+pub fn from_path<T, E>(
+  path: &Path,
+  callback: impl for<'a> FnOnce(&'a mut (dyn Strategy + Send)) -> Result<T, E>,
+) -> Result<T, E>
+where
+  E: From<Error>,
+{
+  from_file(&File::open(path).map_err(Error::IngressIO)?, callback)
+}
+
+pub fn from_file<T, E>(
+  file: &File,
+  callback: impl for<'a> FnOnce(&'a mut (dyn Strategy + Send)) -> Result<T, E>,
+) -> Result<T, E>
+where
+  E: From<Error>,
+{
+  from_fd(file.as_fd(), callback)
+}
+
+pub fn from_fd<T, E>(
+  fd: BorrowedFd,
+  callback: impl for<'a> FnOnce(&'a mut (dyn Strategy + Send)) -> Result<T, E>,
+) -> Result<T, E>
+where
+  E: From<Error>,
+{
+  let mut file_wrapper = unsafe { ManuallyDrop::new(File::from_raw_fd(fd.as_raw_fd())) };
+  match file_wrapper.stream_position() {
+    Ok(skip) => {
+      let mapping = map_file(&file_wrapper, skip)?;
+      let content = mapping_content(&mapping);
+      match compress::sniff(content) {
+        // Compressed input can only be decoded sequentially.
+        Some(target) => callback(&mut ReadStrategy::new(Decoder::new(target, content).map_err(Error::IngressIO)?)),
+        None => callback(&mut MapStrategy::new(content)),
+      }
+    },
+    // TODO(rust): check instead for ErrorKind::NotSeekable once io_error_more is stabilised.
+    Err(err) if err.raw_os_error() == Some(29) /* ESPIPE */ => {
+      // Put back the bytes consumed in sniffing.
+      let mut magic = Vec::with_capacity(compress::MAGIC_LEN);
+      (&*file_wrapper).take(compress::MAGIC_LEN as u64).read_to_end(&mut magic).map_err(Error::IngressIO)?;
+      let target = compress::sniff(&magic);
+      let src = Cursor::new(magic).chain(&*file_wrapper);
+      match target {
+        Some(target) => callback(&mut ReadStrategy::new(Decoder::new(target, BufReader::new(src)).map_err(Error::IngressIO)?)),
+        None => callback(&mut ReadStrategy::new(src)),
+      }
+    },
+    Err(err) => Err(Error::IngressIO(err).into()),
+  }
+}
+
+// The sizes given by the pax extended headers seen so far while splitting an
+// archive, which take precedence over the size fields of the members they
+// apply to (allowing for members too large for the field).
+#[derive(Debug, Default)]
+struct PaxSizes {
+  // From the most recent g record.
+  global: Option<u64>,
+  // From x records since the last member. Some(None) if they cancel the
+  // global size.
+  local: Option<Option<u64>>,
+}
+
+impl PaxSizes {
+  // Returns the length of the content following a header, both as is and
+  // including padding to a whole number of blocks. Extension records always
+  // go by their own size field; any other header consumes the local size.
+  fn content_len(&mut self, header: tar::Header, header_offset: usize) -> Result<(u64, usize), Error> {
+    let field_offset = header_offset + 124;
+    let pax_size = match header.is_prefix() || header.is_pax_header() {
+      true => None,
+      false => self.local.take().unwrap_or(self.global),
+    };
+    let content_len = match pax_size {
+      Some(len) => len,
+      None => header
+        .content_len()
+        .map_err(|err| Error::malformed(field_offset + err.0, Malformation::BadNumericField("size", err)))?,
+    };
+    let padded_len = content_len
+      .checked_next_multiple_of(512)
+      .and_then(|len| usize::try_from(len).ok())
+      .ok_or(Error::malformed(field_offset, Malformation::OversizedMember(content_len)))?;
+    Ok((content_len, padded_len))
+  }
+
+  // Takes note of any size given by an x or g record, whose content (without
+  // padding) starts at content_offset. Other records are ignored.
+  fn update(&mut self, header: tar::Header, content: &[u8], content_offset: usize) -> Result<(), Error> {
+    if !header.is_pax_header() {
+      return Ok(());
+    }
+    let size = tar::pax::size(content).map_err(|tar::pax::ParsePaxError(offset, msg)| {
+      Error::malformed(content_offset + offset, Malformation::BadPaxRecord(msg))
+    })?;
+    match (header.is_global(), size) {
+      (_, None) => {}
+      (false, Some(size)) => self.local = Some(size),
+      (true, Some(size)) => self.global = size,
+    }
+    Ok(())
+  }
+}
+
+// Provides the entire input as a single slice, without splitting it into
+// frames. Seekable inputs are mapped into memory; anything else, including
+// compressed input, is read in its entirety.
+pub fn content_from_stdin<T, E>(callback: impl FnOnce(&[u8]) -> Result<T, E>) -> Result<T, E>
+where
+  E: From<Error>,
+{
+  content_from_fd(std::io::stdin().lock().as_fd(), callback)
+}
+
+pub fn content_from_path<T, E>(path: &Path, callback: impl FnOnce(&[u8]) -> Result<T, E>) -> Result<T, E>
+where
+  E: From<Error>,
+{
+  content_from_fd(File::open(path).map_err(Error::IngressIO)?.as_fd(), callback)
+}
+
+pub fn content_from_fd<T, E>(fd: BorrowedFd, callback: impl FnOnce(&[u8]) -> Result<T, E>) -> Result<T, E>
+where
+  E: From<Error>,
+{
+  let mut file_wrapper = unsafe { ManuallyDrop::new(File::from_raw_fd(fd.as_raw_fd())) };
+  match file_wrapper.stream_position() {
+    Ok(skip) => {
+      let mapping = map_file(&file_wrapper, skip)?;
+      match decompress(mapping_content(&mapping))? {
+        Some(content) => callback(&content),
+        None => callback(mapping_content(&mapping)),
+      }
+    },
+    Err(err) if err.raw_os_error() == Some(29) /* ESPIPE */ => {
+      let mut content = Vec::new();
+      file_wrapper.read_to_end(&mut content).map_err(Error::IngressIO)?;
+      match decompress(&content)? {
+        Some(content) => callback(&content),
+        None => callback(&content),
+      }
+    },
+    Err(err) => Err(Error::IngressIO(err).into()),
+  }
+}
+
+// Returns None if content isn't compressed.
+fn decompress(content: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+  let Some(target) = compress::sniff(content) else { return Ok(None) };
+  let mut decompressed = Vec::new();
+  Decoder::new(target, content)
+    .and_then(|mut decoder| decoder.read_to_end(&mut decompressed))
+    .map_err(Error::IngressIO)?;
+  Ok(Some(decompressed))
+}
+
+// Zero-length files can't be mapped, so they are represented by None.
+fn map_file(file: &File, skip: u64) -> Result<Option<Mmap>, Error> {
+  if file.metadata().map_err(Error::IngressIO)?.len() <= skip {
+    return Ok(None);
+  }
+  Ok(Some(unsafe { MmapOptions::new().offset(skip).map(file) }.map_err(Error::IngressIO)?))
+}
+
+fn mapping_content(mapping: &Option<Mmap>) -> &[u8] {
+  match mapping {
+    Some(mapping) => mapping.as_ref(),
+    None => &[],
+  }
+}
+
+// Splits a frame's content into the head and tail that characterise it. If
+// it's no longer than head_and_tail_len, the whole content is the head, and
+// there is no separate tail.
+pub(crate) fn head_and_tail(frame_content: &[u8], head_and_tail_len: usize) -> (&[u8], Option<&[u8]>) {
+  if frame_content.len() <= head_and_tail_len {
+    (frame_content, None)
+  } else {
+    (&frame_content[..head_and_tail_len], Some(&frame_content[frame_content.len() - head_and_tail_len..]))
+  }
+}
+
+// Shingleprints a frame's head and tail, as split by head_and_tail, unless
+// the cache already has them.
+fn frame_prints(
+  head: &[u8],
+  tail: Option<&[u8]>,
+  config: &ShingleprintConfig,
+  cache: Option<&Cache>,
+) -> (Shingleprint, Shingleprint) {
+  if let Some(prints) = cache.and_then(|cache| cache.get(head, tail)) {
+    return prints.clone();
+  }
+  let head_sp = shingleprint(head, config);
+  let tail_sp = match tail {
+    Some(tail) => shingleprint(tail, config),
+    None => head_sp.clone(),
+  };
+  (head_sp, tail_sp)
+}
+
+pub trait Strategy: fmt::Debug {
+  // Splits the archive into frames and shingleprints them, sending each one
+  // to frames_out. Frames are not necessarily sent in archive order.
+  fn scan(&mut self, params: &Params, frames_out: Sender<Frame>) -> Result<(), Error> {
+    self.scan_with_cache(params, None, frames_out)
+  }
+  // As scan, but taking shingleprints from the cache for frames it has.
+  // The cache must be compatible with params.
+  fn scan_with_cache(&mut self, params: &Params, cache: Option<&Cache>, frames_out: Sender<Frame>) -> Result<(), Error>;
+  // As scan_with_cache, but collecting the frames in archive order.
+  fn scan_sorted(&mut self, params: &Params, cache: Option<&Cache>) -> Result<Vec<Frame>, Error> {
+    let (frames_out, frames_in) = channel::unbounded();
+    self.scan_with_cache(params, cache, frames_out)?;
+    let mut frames: Vec<Frame> = frames_in.into_iter().collect();
+    // Shingleprinting threads deliver frames out of order.
+    frames.sort_by_key(|frame| frame.bounds.start);
+    Ok(frames)
+  }
+  // The complete archive content, which frame bounds are relative to.
+  // Only valid once scan has returned successfully.
+  fn content(&self) -> &[u8];
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compress::Encoder;
+  use crate::params::Target;
+  use crate::util::testing::{member, random_bytes, END_OF_ARCHIVE};
+  use std::io::Write;
+  use std::ops::Range;
+
+  fn archive() -> Vec<u8> {
+    [
+      member(b"a.txt", b'0', b"hello"),
+      member(b"b.bin", b'0', &random_bytes(1, 100000)),
+      END_OF_ARCHIVE.to_vec(),
+    ]
+    .concat()
+  }
+
+  fn compress(target: Target, input: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new(target, compress::default_level(target), Vec::new()).unwrap();
+    encoder.write_all(input).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  fn scan(strategy: &mut (dyn Strategy + Send)) -> Result<(Vec<u8>, Vec<Range<usize>>), Error> {
+    let frames = strategy.scan_sorted(&Params::default(), None)?;
+    Ok((strategy.content().to_vec(), frames.into_iter().map(|frame| frame.bounds).collect()))
+  }
+
+  fn temp_file(content: &[u8]) -> File {
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(content).unwrap();
+    file.rewind().unwrap();
+    file
+  }
+
+  // Runs f on the read end of a pipe being fed content.
+  fn with_pipe<T>(content: &[u8], f: impl FnOnce(BorrowedFd) -> T) -> T {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    std::thread::scope(|scope| {
+      scope.spawn(move || writer.write_all(content).unwrap());
+      f(reader.as_fd())
+    })
+  }
+
+  #[test]
+  fn test_compressed_input() {
+    let archive = archive();
+    let expected = scan(&mut MapStrategy::new(&archive)).unwrap();
+    assert_eq!(expected.1.len(), 2);
+    for target in Target::ALL {
+      let compressed = compress(target, &archive);
+      assert_eq!(from_file(&temp_file(&compressed), scan).unwrap(), expected, "{target}");
+      assert_eq!(with_pipe(&compressed, |fd| from_fd(fd, scan)).unwrap(), expected, "{target}");
+      let content = |content: &[u8]| Ok::<_, Error>(content.to_vec());
+      assert_eq!(content_from_fd(temp_file(&compressed).as_fd(), content).unwrap(), archive);
+      assert_eq!(with_pipe(&compressed, |fd| content_from_fd(fd, content)).unwrap(), archive);
+    }
+    // Uncompressed input is still accepted, including through a pipe.
+    assert_eq!(with_pipe(&archive, |fd| from_fd(fd, scan)).unwrap(), expected);
+  }
+
+  #[test]
+  fn test_corrupt_compressed_input() {
+    let mut compressed = compress(Target::Gzip, &archive());
+    let len = compressed.len();
+    compressed[len / 2] ^= 0xff;
+    let result = from_file(&temp_file(&compressed), scan);
+    assert!(matches!(result, Err(Error::IngressIO(_))), "{result:?}");
+  }
+}
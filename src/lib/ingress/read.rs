@@ -0,0 +1,490 @@
+use crate::index::Cache;
+use crate::error::{Error, Malformation};
+use crate::ingress::{frame_prints, head_and_tail, PaxSizes, Strategy};
+use crate::params::Params;
+use crate::shingleprint::ShingleprintConfig;
+use crate::tunables::INGRESS_BUFFER_MEMORY_TARGET;
+use crate::tar;
+use crate::Frame;
+use crossbeam::channel::{self, Receiver, Sender};
+use memmap::Mmap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Ingress strategy for sources that can only be read sequentially, such as
+// pipes. The archive is spooled to a temporary file as it is read, and only
+// the heads and tails of frames are held in memory for shingleprinting.
+pub struct ReadStrategy<R> {
+  src: R,
+  spool: Option<Mmap>,
+}
+
+impl<R> ReadStrategy<R> {
+  pub fn new(src: R) -> Self {
+    Self { src, spool: None }
+  }
+}
+
+impl<R> fmt::Debug for ReadStrategy<R> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("ReadStrategy").finish_non_exhaustive()
+  }
+}
+
+impl<R: Read + Send> Strategy for ReadStrategy<R> {
+  fn scan_with_cache(&mut self, params: &Params, cache: Option<&Cache>, frames_out: Sender<Frame>) -> Result<(), Error> {
+    let mut spool = tempfile::tempfile().map_err(Error::SpoolIO)?;
+    let head_and_tail_len = params.head_and_tail_len;
+    let config = params.shingleprint;
+    let memory = Memory { usage: AtomicUsize::new(0), buffer_cap: head_and_tail_len * 2 };
+    let src = &mut self.src;
+    std::thread::scope(|scope| {
+      let memory = &memory;
+      let (buffers_to_write_out, buffers_to_write_in) = channel::bounded(64);
+      let (buffers_to_shingleprint_out, buffers_to_shingleprint_in) = channel::bounded(64);
+      let (recycled_buffers_out, recycled_buffers_in) = channel::bounded(64);
+      let shingleprinting_threads: Vec<_> = (0..crate::tunables::N_SHINGLEPRINTING_THREADS)
+        .map(|_| {
+          let buffers_in = buffers_to_shingleprint_in.clone();
+          let frames_out = frames_out.clone();
+          let recycled_buffers_out = recycled_buffers_out.clone();
+          scope.spawn(move || shingleprinting_thread(buffers_in, frames_out, recycled_buffers_out, head_and_tail_len, config, cache))
+        })
+        .collect();
+      drop(buffers_to_shingleprint_in);
+      let writing_thread = {
+        let spool = &mut spool;
+        let recycled_buffers_out = recycled_buffers_out.clone();
+        scope.spawn(move || writing_thread(buffers_to_write_in, spool, recycled_buffers_out))
+      };
+      drop(recycled_buffers_out);
+      let reading_result = reading_thread(
+        src,
+        recycled_buffers_in,
+        buffers_to_write_out,
+        buffers_to_shingleprint_out,
+        memory,
+        head_and_tail_len,
+      );
+      // The reading thread's senders have now been dropped, so the companion
+      // threads will finish once they have drained their channels.
+      let writing_result = writing_thread.join().expect("writing thread panicked");
+      let mut shingleprinting_result = Ok(());
+      for thread in shingleprinting_threads {
+        let result = thread.join().expect("shingleprinting thread panicked");
+        shingleprinting_result = shingleprinting_result.and(result);
+      }
+      // If a companion thread failed, the reading thread will only have seen
+      // CompanionThreadDied, so prefer reporting the companion's error.
+      match reading_result {
+        Err(Error::CompanionThreadDied) => writing_result.and(shingleprinting_result).and(reading_result),
+        _ => reading_result.and(writing_result).and(shingleprinting_result),
+      }
+    })?;
+    spool.flush().map_err(Error::SpoolIO)?;
+    self.spool = super::map_file(&spool, 0).map_err(|err| match err {
+      Error::IngressIO(err) => Error::SpoolIO(err),
+      err => err,
+    })?;
+    Ok(())
+  }
+
+  fn content(&self) -> &[u8] {
+    super::mapping_content(&self.spool)
+  }
+}
+
+// Reads exactly len bytes from src and appends them to buf, unless EOF is
+// reached first. Returns the number of bytes read.
+fn read_into(src: &mut impl Read, buf: &mut Vec<u8>, len: usize) -> Result<usize, Error> {
+  let len = u64::try_from(len).unwrap();
+  src.take(len).read_to_end(buf).map_err(Error::IngressIO)
+}
+
+fn reading_thread<'sess>(
+  mut src: impl Read,
+  recycled_buffers_in: Receiver<Arc<Buffer<'sess>>>,
+  buffers_to_write_out: Sender<Arc<Buffer<'sess>>>,
+  buffers_to_shingleprint_out: Sender<FrameBuffers<'sess>>,
+  memory: &'sess Memory,
+  head_and_tail_len: usize,
+) -> Result<(), Error> {
+  let send_to_write = |buf: Arc<Buffer<'sess>>| {
+    buffers_to_write_out.send(buf).map_err(|_| Error::CompanionThreadDied)
+  };
+  let mut frame_start = 0;
+  let mut pax_sizes = PaxSizes::default();
+  'eachframe: loop {
+    // Read TAR headers (and the content of any prefix records or pax
+    // headers) into the head buffer until we know the total frame length.
+    // This usually fits within the buffer's capacity, but pathologically long
+    // prefix records may cause it to grow.
+    let mut head = get_buffer(&recycled_buffers_in, memory);
+    let (frame_end, barrier) = loop {
+      let header_start = frame_start + head.len();
+      let n = read_into(&mut src, &mut head, 512)?;
+      if n == 0 && head.is_empty() {
+        break 'eachframe; // Clean EOF.
+      }
+      if n < 512 {
+        return Err(Error::malformed(header_start + n, Malformation::PrematureEof));
+      }
+      let header: &[u8; 512] = head[head.len() - 512..].try_into().unwrap();
+      let header = tar::Header(header);
+      if header.is_zero() && head.len() == 512 {
+        // Tar implementations stop reading at the first zero block where a
+        // header is expected. Whatever follows isn't part of any frame, but
+        // still needs to be spooled.
+        send_to_write(Arc::new(head))?;
+        loop {
+          let mut chunk = get_buffer(&recycled_buffers_in, memory);
+          let chunk_len = chunk.capacity();
+          if read_into(&mut src, &mut chunk, chunk_len)? == 0 {
+            break 'eachframe;
+          }
+          send_to_write(Arc::new(chunk))?;
+        }
+      }
+      let in_header = header_start - frame_start;
+      let (content_len, padded_content_len) =
+        pax_sizes.content_len(header, header_start).map_err(|err| err.in_member(&head))?;
+      let (is_prefix, is_global, is_pax_header) = (header.is_prefix(), header.is_global(), header.is_pax_header());
+      let mut content_start = header_start + 512;
+      // A GNU sparse member's map may continue in extension blocks between
+      // its header and its content.
+      let mut extended = header.is_extended();
+      while extended {
+        if read_into(&mut src, &mut head, 512)? < 512 {
+          let err = Error::malformed(frame_start + head.len(), Malformation::PrematureEof);
+          return Err(err.in_member(&head));
+        }
+        extended = tar::sparse::SparseExtension(head[head.len() - 512..].try_into().unwrap()).is_extended();
+        content_start += 512;
+      }
+      if is_prefix || is_pax_header {
+        if read_into(&mut src, &mut head, padded_content_len)? < padded_content_len {
+          let err = Error::malformed(frame_start + head.len(), Malformation::PrematureEof);
+          return Err(err.in_member(&head));
+        }
+        let content = &head[content_start - frame_start..][..content_len as usize];
+        pax_sizes
+          .update(header_in(&head, in_header), content, content_start)
+          .map_err(|err| err.in_member(&head))?;
+      }
+      if !is_prefix {
+        match content_start.checked_add(padded_content_len) {
+          Some(frame_end) => break (frame_end, is_global),
+          None => {
+            let err = Error::malformed(header_start + 124, Malformation::OversizedMember(content_len));
+            return Err(err.in_member(&head));
+          }
+        }
+      }
+    };
+
+    let frame_len = frame_end - frame_start;
+    let fb = if frame_len <= head.capacity().max(memory.buffer_cap) {
+      // The head and tail overlap/abutt, and can be stored in the same buffer.
+      // Read the rest of the frame into the head buffer.
+      let remaining = frame_len - head.len();
+      if read_into(&mut src, &mut head, remaining)? < remaining {
+        let err = Error::malformed(frame_start + head.len(), Malformation::PrematureEof);
+        return Err(err.in_member(&head));
+      }
+      let head = Arc::new(head);
+      send_to_write(head.clone())?;
+      FrameBuffers { bounds: frame_start..frame_end, head, tail: None, barrier }
+    } else {
+      // The head and tail don't overlap; there's bytes to discard in between.
+      // Read the rest of the head and ship it off to the writing thread.
+      if head.len() < head_and_tail_len {
+        let remaining = head_and_tail_len - head.len();
+        if read_into(&mut src, &mut head, remaining)? < remaining {
+          let err = Error::malformed(frame_start + head.len(), Malformation::PrematureEof);
+          return Err(err.in_member(&head));
+        }
+      }
+      let head = Arc::new(head);
+      send_to_write(head.clone())?;
+      let tail_start = frame_end - head_and_tail_len;
+      let mut read_so_far = frame_start + head.len();
+      let mut tail = get_buffer(&recycled_buffers_in, memory);
+      if read_so_far > tail_start {
+        // The head buffer grew so large that it overlaps the tail.
+        tail.extend_from_slice(&head[tail_start - frame_start..]);
+      }
+      // Ship intermediate bytes directly to the writing thread.
+      while read_so_far < tail_start {
+        let mut chunk = get_buffer(&recycled_buffers_in, memory);
+        let chunk_len = std::cmp::min(tail_start - read_so_far, chunk.capacity());
+        let n = read_into(&mut src, &mut chunk, chunk_len)?;
+        read_so_far += n;
+        if n < chunk_len {
+          let err = Error::malformed(read_so_far, Malformation::PrematureEof);
+          return Err(err.in_member(&head));
+        }
+        send_to_write(Arc::new(chunk))?;
+      }
+      // Read the rest of the tail. Only the bytes read here need spooling.
+      let tail_spooled_from = tail.len();
+      let remaining = frame_end - read_so_far;
+      let n = read_into(&mut src, &mut tail, remaining)?;
+      if n < remaining {
+        let err = Error::malformed(read_so_far + n, Malformation::PrematureEof);
+        return Err(err.in_member(&head));
+      }
+      if tail_spooled_from == 0 {
+        let tail = Arc::new(tail);
+        send_to_write(tail.clone())?;
+        FrameBuffers { bounds: frame_start..frame_end, head, tail: Some(tail), barrier }
+      } else {
+        let mut rest = get_buffer(&recycled_buffers_in, memory);
+        rest.extend_from_slice(&tail[tail_spooled_from..]);
+        send_to_write(Arc::new(rest))?;
+        FrameBuffers { bounds: frame_start..frame_end, head, tail: Some(Arc::new(tail)), barrier }
+      }
+    };
+    buffers_to_shingleprint_out.send(fb).map_err(|_| Error::CompanionThreadDied)?;
+    frame_start = frame_end;
+  }
+  Ok(())
+}
+
+fn header_in(head: &[u8], offset: usize) -> tar::Header<'_> {
+  tar::Header(head[offset..][..512].try_into().unwrap())
+}
+
+fn get_buffer<'sess>(recycled: &Receiver<Arc<Buffer<'sess>>>, memory: &'sess Memory) -> Buffer<'sess> {
+  // Have we reached our memory usage target already?
+  if memory.usage.load(atomic::Ordering::Relaxed) >= INGRESS_BUFFER_MEMORY_TARGET {
+    // Wait for a recycled buffer.
+    let deadline = Instant::now() + Duration::from_millis(50);
+    // Stops on disconnection or timeout.
+    while let Ok(arc) = recycled.recv_deadline(deadline) {
+      // If another thread still holds a reference to this buffer, it will
+      // be recycled again once that thread is done with it.
+      if let Ok(mut buf) = Arc::try_unwrap(arc) {
+        buf.clear();
+        return buf;
+      }
+    }
+  } else {
+    // See if we can get a recycled buffer without blocking; otherwise we'll allocate a new one.
+    // Stops on disconnection or if it would block.
+    while let Ok(arc) = recycled.try_recv() {
+      if let Ok(mut buf) = Arc::try_unwrap(arc) {
+        buf.clear();
+        return buf;
+      }
+    }
+  }
+  // Can't recycle.
+  Buffer::new(memory)
+}
+
+fn writing_thread<'sess>(
+  buffers_in: Receiver<Arc<Buffer<'sess>>>,
+  mut dest: impl Write,
+  recycled_buffers_out: Sender<Arc<Buffer<'sess>>>,
+) -> Result<(), Error> {
+  while let Ok(buf) = buffers_in.recv() {
+    dest.write_all(&buf).map_err(Error::SpoolIO)?;
+    // If channel is full or disconnected, just deallocate the buffer instead.
+    let _ = recycled_buffers_out.try_send(buf);
+  }
+  Ok(())
+}
+
+fn shingleprinting_thread<'sess>(
+  buffers_in: Receiver<FrameBuffers<'sess>>,
+  frames_out: Sender<Frame>,
+  recycled_buffers_out: Sender<Arc<Buffer<'sess>>>,
+  head_and_tail_len: usize,
+  config: ShingleprintConfig,
+  cache: Option<&Cache>,
+) -> Result<(), Error> {
+  while let Ok(fb) = buffers_in.recv() {
+    let (head_sp, tail_sp) = match &fb.tail {
+      Some(tail) => frame_prints(&fb.head[..head_and_tail_len], Some(&tail[..]), &config, cache),
+      None => {
+        let (head, tail) = head_and_tail(&fb.head, head_and_tail_len);
+        frame_prints(head, tail, &config, cache)
+      }
+    };
+    if let Some(tail) = fb.tail {
+      let _ = recycled_buffers_out.try_send(tail);
+    }
+    let _ = recycled_buffers_out.try_send(fb.head);
+    let frame = Frame { bounds: fb.bounds, head_sp, tail_sp, barrier: fb.barrier };
+    if frames_out.send(frame).is_err() {
+      return Err(Error::CompanionThreadDied);
+    }
+  }
+  Ok(())
+}
+
+#[derive(Debug)]
+struct FrameBuffers<'sess> {
+  bounds: Range<usize>,
+  head: Arc<Buffer<'sess>>,
+  tail: Option<Arc<Buffer<'sess>>>,
+  barrier: bool,
+}
+
+// Accounting for the buffers allocated during one scan.
+#[derive(Debug)]
+struct Memory {
+  usage: AtomicUsize,
+  // Enough for a frame's head and tail together.
+  buffer_cap: usize,
+}
+
+#[derive(Debug)]
+struct Buffer<'sess> {
+  data: Vec<u8>,
+  memory: &'sess Memory,
+}
+
+impl<'sess> Buffer<'sess> {
+  fn new(memory: &'sess Memory) -> Self {
+    memory.usage.fetch_add(memory.buffer_cap, atomic::Ordering::Relaxed);
+    Self {
+      data: Vec::with_capacity(memory.buffer_cap),
+      memory,
+    }
+  }
+}
+
+impl<'sess> Drop for Buffer<'sess> {
+  fn drop(&mut self) {
+    self.memory.usage.fetch_sub(self.memory.buffer_cap, atomic::Ordering::Relaxed);
+  }
+}
+
+impl<'sess> Deref for Buffer<'sess> {
+  type Target = Vec<u8>;
+  fn deref(&self) -> &Vec<u8> {
+    &self.data
+  }
+}
+
+impl<'sess> DerefMut for Buffer<'sess> {
+  fn deref_mut(&mut self) -> &mut Vec<u8> {
+    &mut self.data
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ingress::MapStrategy;
+  use crate::params::Target;
+  use crate::util::testing::{member, random_bytes, END_OF_ARCHIVE};
+
+  fn scan(strategy: &mut dyn Strategy) -> Result<Vec<Frame>, Error> {
+    scan_with(strategy, &Params::default())
+  }
+
+  fn scan_with(strategy: &mut dyn Strategy, params: &Params) -> Result<Vec<Frame>, Error> {
+    strategy.scan_sorted(params, None)
+  }
+
+  // Yields at most a few bytes per read call, to exercise partial reads.
+  struct Trickle<'a>(&'a [u8]);
+
+  impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      let n = buf.len().min(self.0.len()).min(777);
+      buf[..n].copy_from_slice(&self.0[..n]);
+      self.0 = &self.0[n..];
+      Ok(n)
+    }
+  }
+
+  fn archive() -> Vec<u8> {
+    use crate::tar::pax::build_records;
+    use crate::util::testing::{gnu_sparse_member, header};
+    let head_and_tail_len = Params::default().head_and_tail_len;
+    let pax_len = 3 * head_and_tail_len + 512;
+    let sparse_pairs: Vec<_> = (0..10).map(|i| (1024 * i, 512)).collect();
+    [
+      member(b"small", b'0', b"hello"),
+      // A size given by a pax header, rather than the header's size field.
+      member(b"PaxHeaders/pax", b'x', &build_records(&[(b"size", pax_len.to_string().as_bytes())])),
+      header(b"pax", b'0', 0).to_vec(),
+      random_bytes(5, pax_len),
+      member(b"GlobalHead.0", b'g', &build_records(&[(b"comment", &random_bytes(6, 3 * head_and_tail_len))])),
+      member(b"medium", b'0', &random_bytes(1, head_and_tail_len + 100)),
+      member(b"large", b'0', &random_bytes(2, 10 * head_and_tail_len + 1)),
+      // A prefix record so long that the head buffer overlaps the tail.
+      member(b"././@LongLink", b'L', &random_bytes(3, 3 * head_and_tail_len)),
+      member(b"long", b'0', &random_bytes(4, 1000)),
+      member(b"././@LongLink", b'K', b"short"),
+      member(b"link", b'2', b""),
+      // Enough entries to need an extension block.
+      gnu_sparse_member(b"sparse", &sparse_pairs, 10240),
+      END_OF_ARCHIVE.to_vec(),
+      vec![0; 7 * 512],
+      // Anything at all may follow the end-of-archive marker.
+      member(b"after", b'0', &random_bytes(7, 3 * head_and_tail_len)),
+      b"garbage".to_vec(),
+    ]
+    .concat()
+  }
+
+  #[test]
+  fn test_same_as_map() {
+    let archive = archive();
+    for target in Target::ALL {
+      let params = Params::for_target(target);
+      let expected = scan_with(&mut MapStrategy::new(&archive), &params).unwrap();
+      let mut strategy = ReadStrategy::new(Trickle(&archive));
+      let got = scan_with(&mut strategy, &params).unwrap();
+      assert_eq!(strategy.content(), archive);
+      assert_eq!(got.len(), expected.len());
+      assert!(expected.iter().any(|frame| frame.barrier));
+      for (got, expected) in got.iter().zip(&expected) {
+        assert_eq!(got.bounds, expected.bounds);
+        assert_eq!(got.head_sp, expected.head_sp);
+        assert_eq!(got.tail_sp, expected.tail_sp);
+        assert_eq!(got.barrier, expected.barrier);
+      }
+    }
+  }
+
+  #[test]
+  fn test_empty() {
+    let mut strategy = ReadStrategy::new(&b""[..]);
+    assert!(scan(&mut strategy).unwrap().is_empty());
+    assert_eq!(strategy.content(), b"");
+  }
+
+  #[test]
+  fn test_truncated() {
+    let archive = archive();
+    for len in [100, 2000, 30000, 100000] {
+      let (Err(Error::MalformedInput(got)), Err(Error::MalformedInput(expected))) =
+        (scan(&mut ReadStrategy::new(&archive[..len])), scan(&mut MapStrategy::new(&archive[..len])))
+      else {
+        panic!("len {len}: expected MalformedInput");
+      };
+      assert_eq!(got, expected, "len {len}");
+    }
+  }
+
+  #[test]
+  fn test_malformed_length() {
+    let mut archive = archive();
+    archive[124..136].copy_from_slice(b"garbage!!!!\0");
+    let Err(Error::MalformedInput(malformed)) = scan(&mut ReadStrategy::new(&archive[..])) else {
+      panic!("expected MalformedInput");
+    };
+    assert_eq!((malformed.offset, malformed.member.as_deref()), (124, Some(&b"small"[..])));
+    let err = tar::ParseNumericError(0, "invalid octal digit");
+    assert_eq!(malformed.kind, Malformation::BadNumericField("size", err));
+  }
+}
@@ -1,3 +1,34 @@
+#![allow(clippy::missing_safety_doc)]
+
+use std::ops::Range;
+
+pub mod analyze;
+pub mod compress;
+pub mod crush;
+pub mod error;
+pub mod index;
+pub mod ingress;
+pub mod order;
+pub mod params;
+pub mod restore;
 pub mod shingleprint;
+pub mod tar;
+pub mod verify;
 mod tunables;
 mod util;
+
+pub use error::Error;
+
+// A contiguous range of the archive that must be kept together when
+// reordering: one member's header and content, plus any extension records
+// that apply to it.
+#[derive(Clone, Debug)]
+pub struct Frame {
+  pub bounds: Range<usize>,
+  pub head_sp: shingleprint::Shingleprint,
+  pub tail_sp: shingleprint::Shingleprint,
+  // Whether the frame holds a pax global header. These apply to every member
+  // after them, so no other frame may be moved from one side of it to the
+  // other.
+  pub barrier: bool,
+}
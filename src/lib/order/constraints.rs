@@ -0,0 +1,218 @@
+use crate::tar::Member;
+use crate::Frame;
+use std::collections::HashMap;
+
+// Pairs of frames whose relative order must be kept for the reordered
+// archive to extract the same as the original:
+//   - a hard link comes after the member it links to, and before any later
+//     member that replaces that member;
+//   - a member comes after the entries for the directories containing it,
+//     so that they exist with the right metadata by the time it is extracted;
+//   - members with the same name stay in the same order, since the last one
+//     extracted is the one that remains;
+//   - barriers (pax global headers, which apply to every member after them)
+//     stay in place, with the same frames on either side.
+// Frames that can't be parsed as members are treated as barriers, since
+// nothing can be known about what they depend on.
+// Every constraint is between two frames in their original order, so the
+// original order always satisfies them.
+#[derive(Clone, Debug)]
+pub struct Constraints {
+  // For each frame, the frames that must come after it.
+  successors: Vec<Vec<usize>>,
+  // For each frame, how many frames must come before it.
+  n_predecessors: Vec<usize>,
+}
+
+impl Constraints {
+  // Constraints between len frames, which are as yet unconstrained.
+  pub fn none(len: usize) -> Self {
+    Self { successors: vec![Vec::new(); len], n_predecessors: vec![0; len] }
+  }
+
+  // Derives the constraints from the headers of the frames, which must be in
+  // their original order, with bounds relative to content.
+  pub fn new(frames: &[Frame], content: &[u8]) -> Self {
+    let mut constraints = Self::none(frames.len());
+    let mut last_barrier = None;
+    // The most recent frame with each name.
+    let mut latest = HashMap::new();
+    // The hard links to each name since it was last replaced.
+    let mut links_to: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    for (i, frame) in frames.iter().enumerate() {
+      let member = match frame.barrier {
+        true => None,
+        false => Member::parse(&content[frame.bounds.clone()]).ok(),
+      };
+      let Some(member) = member else {
+        for j in last_barrier.map_or(0, |barrier| barrier + 1)..i {
+          constraints.add(j, i);
+        }
+        last_barrier = Some(i);
+        continue;
+      };
+      if let Some(barrier) = last_barrier {
+        constraints.add(barrier, i);
+      }
+      let name = normalise(&member.name);
+      for (len, _) in name.iter().enumerate().filter(|&(_, &byte)| byte == b'/') {
+        if let Some(&j) = latest.get(&name[..len]) {
+          constraints.add(j, i);
+        }
+      }
+      if let Some(&j) = latest.get(name) {
+        constraints.add(j, i);
+      }
+      for j in links_to.remove(name).unwrap_or_default() {
+        constraints.add(j, i);
+      }
+      if member.header.type_flag() == b'1' {
+        let target = normalise(&member.linkname);
+        if let Some(&j) = latest.get(target) {
+          constraints.add(j, i);
+        }
+        links_to.entry(target.to_vec()).or_default().push(i);
+      }
+      latest.insert(name.to_vec(), i);
+    }
+    constraints
+  }
+
+  // Requires that frame before is placed before frame after, which must
+  // also be the case in the original order.
+  pub fn add(&mut self, before: usize, after: usize) {
+    debug_assert!(before < after);
+    self.successors[before].push(after);
+    self.n_predecessors[after] += 1;
+  }
+
+  // The frames that must come after the given one (possibly repeated).
+  pub fn successors(&self, frame: usize) -> &[usize] {
+    &self.successors[frame]
+  }
+
+  // How many frames must come before the given one (counting repeats).
+  pub fn n_predecessors(&self, frame: usize) -> usize {
+    self.n_predecessors[frame]
+  }
+
+  pub fn len(&self) -> usize {
+    self.successors.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.successors.is_empty()
+  }
+}
+
+// Strips what tar implementations ignore when extracting: leading "./" and
+// "/" components, and trailing slashes (which directory names often have).
+fn normalise(mut name: &[u8]) -> &[u8] {
+  loop {
+    if let Some(rest) = name.strip_prefix(b"./") {
+      name = rest;
+    } else if let Some(rest) = name.strip_prefix(b"/") {
+      name = rest;
+    } else {
+      break;
+    }
+  }
+  while let Some(rest) = name.strip_suffix(b"/") {
+    name = rest;
+  }
+  name
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shingleprint::Shingleprint;
+  use crate::util::testing::{entry, member};
+
+  // Constraints for frames each holding one of the given members, and
+  // whether each is a barrier.
+  fn constraints(members: &[(Vec<u8>, bool)]) -> Vec<Vec<usize>> {
+    let content = members.iter().map(|(member, _)| &member[..]).collect::<Vec<_>>().concat();
+    let mut offset = 0;
+    let frames: Vec<_> = members
+      .iter()
+      .map(|(member, barrier)| {
+        offset += member.len();
+        let sp = Shingleprint::from_hashes(&[], &Default::default()).unwrap();
+        Frame { bounds: offset - member.len()..offset, head_sp: sp.clone(), tail_sp: sp, barrier: *barrier }
+      })
+      .collect();
+    let constraints = Constraints::new(&frames, &content);
+    // As lists of predecessors, for readability.
+    let mut predecessors = vec![Vec::new(); frames.len()];
+    for i in 0..frames.len() {
+      for &j in constraints.successors(i) {
+        predecessors[j].push(i);
+      }
+      assert_eq!(constraints.n_predecessors(i), predecessors[i].len());
+    }
+    for list in &mut predecessors {
+      list.sort();
+      list.dedup();
+    }
+    predecessors
+  }
+
+  fn file(name: &[u8]) -> (Vec<u8>, bool) {
+    (member(name, b'0', b"content"), false)
+  }
+
+  fn entry_with(name: &[u8], type_flag: u8, linkname: &[u8]) -> (Vec<u8>, bool) {
+    (entry(name, type_flag, 0o755, linkname, b""), false)
+  }
+
+  #[test]
+  fn test_unrelated() {
+    assert_eq!(constraints(&[file(b"a"), file(b"b"), file(b"c")]), [vec![], vec![], vec![]]);
+  }
+
+  #[test]
+  fn test_directories() {
+    let members = [
+      file(b"a/early"),
+      entry_with(b"./a/", b'5', b""),
+      entry_with(b"a/b/", b'5', b""),
+      file(b"a/b/c"),
+      file(b"a/d"),
+      file(b"ab/e"),
+    ];
+    assert_eq!(constraints(&members), [vec![], vec![], vec![1], vec![1, 2], vec![1], vec![]]);
+  }
+
+  #[test]
+  fn test_same_name() {
+    let members = [file(b"a"), file(b"b"), file(b"./a"), file(b"a")];
+    assert_eq!(constraints(&members), [vec![], vec![], vec![0], vec![2]]);
+  }
+
+  #[test]
+  fn test_hard_links() {
+    let members = [
+      file(b"target"),
+      entry_with(b"link1", b'1', b"./target"),
+      entry_with(b"link2", b'1', b"link1"),
+      // Replaces the target, so must stay after the links to it.
+      file(b"target"),
+      entry_with(b"link3", b'1', b"target"),
+      // A link whose target isn't in the archive.
+      entry_with(b"link4", b'1', b"elsewhere"),
+      // Symbolic links don't need their targets to exist.
+      entry_with(b"symlink", b'2', b"target"),
+    ];
+    assert_eq!(constraints(&members), [vec![], vec![0], vec![1], vec![0, 1], vec![3], vec![], vec![]]);
+  }
+
+  #[test]
+  fn test_barriers() {
+    let members = [file(b"a"), file(b"b"), (member(b"g", b'g', b"9 path=x\n"), true), file(b"c"), file(b"d")];
+    assert_eq!(constraints(&members), [vec![], vec![], vec![0, 1], vec![2], vec![2]]);
+    // Unparseable frames are also kept in place.
+    let members = [file(b"a"), (vec![0; 100], false), file(b"b")];
+    assert_eq!(constraints(&members), [vec![], vec![0], vec![1]]);
+  }
+}
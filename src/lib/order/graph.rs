@@ -0,0 +1,156 @@
+use crate::shingleprint::lsh::LshIndex;
+use crate::tunables::MAX_CANDIDATES_PER_FRAME;
+use crate::Frame;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Edge {
+  pub to: usize,
+  // Estimated Jaccard similarity between the source frame's tail and the
+  // destination frame's head.
+  pub similarity: f64,
+}
+
+// For each frame, the frames that would make good successors to it: those
+// whose head most resembles its tail.
+#[derive(Debug)]
+pub struct CandidateGraph {
+  // Invariant: each list is sorted by descending similarity, contains no
+  // edges of zero similarity and no self-loops, and has at most
+  // MAX_CANDIDATES_PER_FRAME entries.
+  successors: Vec<Vec<Edge>>,
+}
+
+impl CandidateGraph {
+  // Compares every frame's tail with every other frame's head.
+  pub fn all_pairs(frames: &[Frame]) -> Self {
+    let successors = frames
+      .iter()
+      .enumerate()
+      .map(|(from, from_frame)| {
+        let edges = frames.iter().enumerate().filter(|&(to, _)| to != from).map(|(to, to_frame)| Edge {
+          to,
+          similarity: from_frame.tail_sp.estimate_jaccard(&to_frame.head_sp),
+        });
+        best_edges(edges)
+      })
+      .collect();
+    Self { successors }
+  }
+
+  // Compares each frame's tail only with the heads of frames that share an
+  // LSH band with it, which takes sub-quadratic time but may miss some
+  // similar pairs.
+  pub fn with_lsh(frames: &[Frame]) -> Self {
+    let mut index = LshIndex::new();
+    for (id, frame) in frames.iter().enumerate() {
+      index.insert(id, &frame.head_sp);
+    }
+    let successors = frames
+      .iter()
+      .enumerate()
+      .map(|(from, from_frame)| {
+        let candidates = index.candidates(&from_frame.tail_sp, from);
+        let edges = candidates.into_iter().filter(|&to| to != from).map(|to| Edge {
+          to,
+          similarity: from_frame.tail_sp.estimate_jaccard(&frames[to].head_sp),
+        });
+        best_edges(edges)
+      })
+      .collect();
+    Self { successors }
+  }
+
+  pub fn successors(&self, from: usize) -> &[Edge] {
+    &self.successors[from]
+  }
+
+  pub fn len(&self) -> usize {
+    self.successors.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.successors.is_empty()
+  }
+}
+
+// Retains the MAX_CANDIDATES_PER_FRAME edges of highest similarity, ties
+// being broken in favour of the lower frame index.
+fn best_edges(edges: impl Iterator<Item = Edge>) -> Vec<Edge> {
+  let mut best: Vec<Edge> = edges.filter(|edge| edge.similarity > 0.0).collect();
+  best.sort_by(|a, b| b.similarity.total_cmp(&a.similarity).then(a.to.cmp(&b.to)));
+  best.truncate(MAX_CANDIDATES_PER_FRAME);
+  best
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shingleprint::{shingleprint, ShingleprintConfig};
+
+  fn frame(head: &[u8], tail: &[u8]) -> Frame {
+    let config = ShingleprintConfig::default();
+    Frame { bounds: 0..0, head_sp: shingleprint(head, &config), tail_sp: shingleprint(tail, &config), barrier: false }
+  }
+
+  #[test]
+  fn test_all_pairs() {
+    let frames = [
+      frame(b"unrelated head number one", b"The quick brown fox jumps over the lazy dog."),
+      frame(b"The quick brown fox jumps over the lazy cat.", b"unrelated tail number one"),
+      frame(b"The quick brown fox jumps over the lazy dog.", b"The quick brown fox jumps over the lazy dog."),
+      frame(b"Lorem ipsum dolor sit amet", b"Lorem ipsum dolor sit amet"),
+    ];
+    let graph = CandidateGraph::all_pairs(&frames);
+    assert_eq!(graph.len(), 4);
+    let successors: Vec<_> = graph.successors(0).iter().map(|edge| edge.to).collect();
+    assert_eq!(successors, [2, 1]);
+    assert_eq!(graph.successors(0)[0].similarity, 1.0);
+    assert!(graph.successors(1).is_empty());
+    // No self-loops, even though frame 2's head and tail are identical.
+    let successors: Vec<_> = graph.successors(2).iter().map(|edge| edge.to).collect();
+    assert_eq!(successors, [1]);
+    assert!(graph.successors(3).is_empty());
+  }
+
+  #[test]
+  fn test_with_lsh_subset_of_all_pairs() {
+    use crate::util::testing::random_bytes;
+    // Clusters of frames whose heads and tails are all variations on the
+    // same content. They are small enough that every other member of a
+    // cluster is a candidate successor.
+    const CLUSTER_LEN: usize = 5;
+    const _: () = assert!(CLUSTER_LEN <= MAX_CANDIDATES_PER_FRAME);
+    let cluster = |i: usize| i / CLUSTER_LEN;
+    let frames: Vec<_> = (0..60)
+      .map(|i| {
+        let mut head = random_bytes(cluster(i) as u64 + 1, 1000);
+        let mut tail = head.clone();
+        head[(i * 37) % 1000] ^= 1;
+        tail[(i * 53 + 500) % 1000] ^= 1;
+        frame(&head, &tail)
+      })
+      .chain([frame(b"a lone frame with a head", b"and a tail unlike anything else")])
+      .collect();
+    let exact = CandidateGraph::all_pairs(&frames);
+    let approx = CandidateGraph::with_lsh(&frames);
+    assert_eq!(approx.len(), exact.len());
+    for from in 0..60 {
+      for edge in approx.successors(from) {
+        assert!(exact.successors(from).contains(edge), "{from} -> {edge:?} not in all pairs");
+      }
+      assert!(approx.successors(from).iter().any(|edge| cluster(edge.to) == cluster(from)), "frame {from}");
+    }
+    assert!(approx.successors(60).is_empty());
+  }
+
+  #[test]
+  fn test_best_edges() {
+    let edges = (0..MAX_CANDIDATES_PER_FRAME * 2).map(|to| Edge { to, similarity: (to % 3) as f64 });
+    let best = best_edges(edges);
+    assert_eq!(best.len(), MAX_CANDIDATES_PER_FRAME);
+    assert!(best.iter().all(|edge| edge.similarity > 0.0));
+    assert!(best.windows(2).all(|w| w[0].similarity > w[1].similarity
+      || (w[0].similarity == w[1].similarity && w[0].to < w[1].to)));
+    assert_eq!(best[0], Edge { to: 2, similarity: 2.0 });
+  }
+}
@@ -0,0 +1,180 @@
+use crate::params::Params;
+use crate::Frame;
+use std::collections::VecDeque;
+
+pub mod constraints;
+pub mod graph;
+
+use constraints::Constraints;
+use graph::CandidateGraph;
+
+// Returns a permutation of frame indices: the order in which the frames
+// should be written out, which satisfies the constraints.
+pub fn order(frames: &[Frame], params: &Params, constraints: &Constraints) -> Vec<usize> {
+  greedy_chain(&CandidateGraph::with_lsh(frames), frames, params, constraints)
+}
+
+// Builds chains of frames by greedy nearest-neighbour search: each frame is
+// followed by the unplaced frame most similar to one of the recently placed
+// frames that are still within the compressor's window, preferring the more
+// recent on ties. When no such frame exists, a new chain is started from the
+// earliest unplaced frame, so that frames without similar peers keep their
+// original relative order.
+// Frames only become candidates once all the frames the constraints require
+// to come before them have been placed. The earliest unplaced frame is always
+// such a frame, since constraints only require what the original order has.
+pub fn greedy_chain(graph: &CandidateGraph, frames: &[Frame], params: &Params, constraints: &Constraints) -> Vec<usize> {
+  debug_assert_eq!(graph.len(), frames.len());
+  debug_assert_eq!(constraints.len(), frames.len());
+  let mut placed = vec![false; graph.len()];
+  // Number of frames yet to be placed that must come before each frame.
+  let mut pending: Vec<_> = (0..graph.len()).map(|i| constraints.n_predecessors(i)).collect();
+  let mut permutation = Vec::with_capacity(graph.len());
+  let mut next_unplaced = 0;
+  // Most recently placed last. Always holds at least the last placed frame.
+  let mut recent = VecDeque::new();
+  // Total length of the recent frames, excluding the oldest.
+  let mut len_after_oldest = 0;
+  while permutation.len() < graph.len() {
+    let mut best = None;
+    for &from in recent.iter().rev() {
+      let edge = graph.successors(from).iter().find(|edge| !placed[edge.to] && pending[edge.to] == 0);
+      if let Some(edge) = edge.filter(|edge| best.is_none_or(|(_, similarity)| edge.similarity > similarity)) {
+        best = Some((edge.to, edge.similarity));
+      }
+    }
+    let current = match best {
+      Some((to, _)) => to,
+      None => {
+        while placed[next_unplaced] {
+          next_unplaced += 1;
+        }
+        debug_assert_eq!(pending[next_unplaced], 0);
+        next_unplaced
+      }
+    };
+    placed[current] = true;
+    permutation.push(current);
+    for &successor in constraints.successors(current) {
+      pending[successor] -= 1;
+    }
+    if !recent.is_empty() {
+      len_after_oldest += frames[current].bounds.len();
+    }
+    recent.push_back(current);
+    // Forget frames that have fallen out of the window.
+    while recent.len() > 1 && (len_after_oldest >= params.window_len || recent.len() > params.max_recent_frames) {
+      recent.pop_front();
+      len_after_oldest -= frames[recent[0]].bounds.len();
+    }
+  }
+  permutation
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shingleprint::{shingleprint, ShingleprintConfig};
+
+  fn frame(head: &[u8], tail: &[u8]) -> Frame {
+    let config = ShingleprintConfig::default();
+    Frame { bounds: 0..0, head_sp: shingleprint(head, &config), tail_sp: shingleprint(tail, &config), barrier: false }
+  }
+
+  fn unconstrained(frames: &[Frame], params: &Params) -> Vec<usize> {
+    order(frames, params, &Constraints::none(frames.len()))
+  }
+
+  fn is_permutation(permutation: &[usize], len: usize) -> bool {
+    let mut sorted = permutation.to_vec();
+    sorted.sort();
+    sorted == (0..len).collect::<Vec<_>>()
+  }
+
+  #[test]
+  fn test_chains_similar_frames() {
+    const FOX: &[u8] = b"The quick brown fox jumps over the lazy dog.";
+    const LOREM: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit.";
+    const HELLO: &[u8] = b"Hello, world! Hello, world! Hello, world!";
+    let frames = [
+      frame(FOX, LOREM),
+      frame(HELLO, HELLO),
+      frame(b"something else entirely", FOX),
+      frame(LOREM, b"and something else again"),
+    ];
+    assert_eq!(unconstrained(&frames, &Params::default()), [0, 3, 1, 2]);
+  }
+
+  #[test]
+  fn test_prefers_most_similar() {
+    let frames = [
+      frame(b"", b"The quick brown fox jumps over the lazy dog."),
+      frame(b"The quick brown fox jumps over the lazy cat.", b"1111111111111111111"),
+      frame(b"The quick brown fox jumps over the lazy dog!", b"2222222222222222222"),
+    ];
+    assert_eq!(unconstrained(&frames, &Params::default()), [0, 2, 1]);
+  }
+
+  #[test]
+  fn test_window() {
+    const FOX: &[u8] = b"The quick brown fox jumps over the lazy dog.";
+    let frame = |bounds, head: &[u8], tail: &[u8]| Frame { bounds, ..frame(head, tail) };
+    let frames = [
+      frame(0..1000, b"", FOX),
+      frame(1000..2000, FOX, b"1111111111111111111"),
+      frame(2000..3000, b"2222222222222222222", b""),
+      frame(3000..4000, b"The quick brown fox jumps over the lazy cat.", b""),
+    ];
+    // Once frame 1 has been placed, frame 0 is only within a large window,
+    // so its similarity to frame 3 is only worth exploiting then.
+    let small = Params { window_len: 1000, ..Params::default() };
+    let large = Params { window_len: 1000000, ..Params::default() };
+    assert_eq!(unconstrained(&frames, &small), [0, 1, 2, 3]);
+    assert_eq!(unconstrained(&frames, &large), [0, 1, 3, 2]);
+    let one_recent = Params { max_recent_frames: 1, ..large };
+    assert_eq!(unconstrained(&frames, &one_recent), [0, 1, 2, 3]);
+  }
+
+  #[test]
+  fn test_constraints() {
+    const FOX: &[u8] = b"The quick brown fox jumps over the lazy dog.";
+    const LOREM: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit.";
+    let frames = [
+      frame(b"", FOX),
+      frame(b"something else entirely", b""),
+      frame(b"and something else again", b""),
+      frame(FOX, LOREM),
+      frame(LOREM, b""),
+    ];
+    let params = Params::default();
+    assert_eq!(unconstrained(&frames, &params), [0, 3, 4, 1, 2]);
+    // Frame 3 is still placed as soon as it's allowed to be.
+    let mut constraints = Constraints::none(frames.len());
+    constraints.add(1, 3);
+    assert_eq!(order(&frames, &params, &constraints), [0, 1, 3, 4, 2]);
+    constraints.add(2, 3);
+    assert_eq!(order(&frames, &params, &constraints), [0, 1, 2, 3, 4]);
+    let mut constraints = Constraints::none(frames.len());
+    constraints.add(2, 4);
+    assert_eq!(order(&frames, &params, &constraints), [0, 3, 1, 2, 4]);
+  }
+
+  #[test]
+  fn test_unrelated_frames_keep_order() {
+    let frames: Vec<_> = (0..5u8).map(|i| frame(&[i; 20], &[i + 100; 20])).collect();
+    assert_eq!(unconstrained(&frames, &Params::default()), [0, 1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn test_permutation() {
+    use crate::util::testing::random_bytes;
+    let frames: Vec<_> = (0..50)
+      .map(|i| {
+        let content = random_bytes(i % 7, 200);
+        frame(&content[..100], &content[100..])
+      })
+      .collect();
+    assert!(is_permutation(&unconstrained(&frames, &Params::default()), frames.len()));
+    assert!(unconstrained(&[], &Params::default()).is_empty());
+  }
+}
@@ -0,0 +1,124 @@
+use crate::shingleprint::ShingleprintConfig;
+use crate::tunables::{MAX_HEAD_AND_TAIL_LEN, MAX_RECENT_FRAMES, MIN_HEAD_AND_TAIL_LEN};
+use std::fmt;
+use std::str::FromStr;
+
+// The compressor that a crushed archive is destined for.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Target {
+  #[default]
+  Gzip,
+  Bzip2,
+  Zstd,
+  Xz,
+}
+
+impl Target {
+  pub const ALL: [Target; 4] = [Target::Gzip, Target::Bzip2, Target::Zstd, Target::Xz];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Target::Gzip => "gzip",
+      Target::Bzip2 => "bzip2",
+      Target::Zstd => "zstd",
+      Target::Xz => "xz",
+    }
+  }
+
+  // How far back in the stream the compressor can find redundancy, at its
+  // default settings.
+  pub fn window_len(self) -> usize {
+    match self {
+      // DEFLATE's sliding window.
+      Target::Gzip => 32 * 1024,
+      // bzip2 compresses independent blocks of up to 900 kB.
+      Target::Bzip2 => 900 * 1000,
+      // zstd's window at its default level (3) is 2 MiB, but grows to 8 MiB
+      // at moderate levels.
+      Target::Zstd => 8 * 1024 * 1024,
+      // xz's dictionary at its default preset (-6).
+      Target::Xz => 8 * 1024 * 1024,
+    }
+  }
+}
+
+impl fmt::Display for Target {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+#[derive(Debug)]
+pub struct ParseTargetError;
+
+impl fmt::Display for ParseTargetError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let names: Vec<_> = Target::ALL.iter().map(|target| target.name()).collect();
+    write!(f, "expected one of: {}", names.join(", "))
+  }
+}
+
+impl std::error::Error for ParseTargetError {}
+
+impl FromStr for Target {
+  type Err = ParseTargetError;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Target::ALL.into_iter().find(|target| target.name() == s).ok_or(ParseTargetError)
+  }
+}
+
+// Settings for the scanning and ordering stages.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Params {
+  // Bytes of history visible to the compressor.
+  pub window_len: usize,
+  // Frames are characterised by shingleprints of their first and last this-many bytes.
+  pub head_and_tail_len: usize,
+  // When choosing the next frame, consider similarity to up to this many of
+  // the most recently placed frames, if they are still within the window.
+  pub max_recent_frames: usize,
+  pub shingleprint: ShingleprintConfig,
+}
+
+impl Params {
+  pub fn for_target(target: Target) -> Self {
+    let window_len = target.window_len();
+    Self {
+      window_len,
+      // Two adjacent frames' tail and head should fit in the window together.
+      head_and_tail_len: (window_len / 2).clamp(MIN_HEAD_AND_TAIL_LEN, MAX_HEAD_AND_TAIL_LEN),
+      max_recent_frames: MAX_RECENT_FRAMES,
+      shingleprint: ShingleprintConfig::default(),
+    }
+  }
+}
+
+impl Default for Params {
+  fn default() -> Self {
+    Self::for_target(Target::default())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_target() {
+    for target in Target::ALL {
+      assert_eq!(target.to_string().parse::<Target>().ok(), Some(target));
+    }
+    assert!("lz4".parse::<Target>().is_err());
+  }
+
+  #[test]
+  fn test_head_and_tail_len() {
+    assert_eq!(Params::for_target(Target::Gzip).head_and_tail_len, 16 * 1024);
+    assert_eq!(Params::for_target(Target::Bzip2).head_and_tail_len, 450 * 1000);
+    assert_eq!(Params::for_target(Target::Xz).head_and_tail_len, MAX_HEAD_AND_TAIL_LEN);
+    for target in Target::ALL {
+      let params = Params::for_target(target);
+      assert!(2 * params.head_and_tail_len <= params.window_len);
+    }
+  }
+}
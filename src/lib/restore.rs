@@ -0,0 +1,290 @@
+// Restoration of a crushed archive to its original byte sequence.
+//
+// Compressed archives are decompressed before they are crushed, so it is the
+// uncompressed original that is restored; the compression is not recorded.
+//
+// A crushed archive consists of the reordered frames, an end-of-archive
+// marker, and then a trailing member holding the restore data. Since tar
+// implementations stop reading at the end-of-archive marker, the trailing
+// member is invisible to them.
+//
+// The restore data is laid out as follows, with all integers little-endian:
+//   - RESTORE_MAGIC
+//   - u64 format version (RESTORE_VERSION)
+//   - u64 length of the original archive
+//   - u64 number of frames
+//   - for each frame, in crushed order: u64 offset in the original archive, u64 length
+//   - u64 offset in the original archive of the trailing bytes, u64 length
+//   - the trailing bytes
+//   - zero padding, up to 16 bytes short of a block boundary
+//   - u64 length of the crushed frames, i.e. the offset of the end-of-archive marker
+//   - RESTORE_MAGIC
+// The last 16 bytes of the crushed archive therefore locate the restore data.
+// The trailing bytes are whatever follows the original's end-of-archive
+// marker, trimmed of leading and trailing zeroes; they are kept here so that
+// they stay out of sight of tar implementations. Any other bytes of the
+// original archive not covered by a frame are zeroes.
+
+use crate::error::{Error, Malformation};
+use crate::tar;
+use std::io::Write;
+use std::ops::Range;
+
+const RESTORE_MAGIC: &[u8; 8] = b"TARCRUSH";
+const RESTORE_VERSION: u64 = 1;
+const RESTORE_MEMBER_NAME: &[u8] = b"TARCRUSH.restore";
+const FOOTER_LEN: usize = 16;
+
+// Writes the end-of-archive marker and restore data, given the original
+// archive and the bounds within it of each frame that has been written, in
+// the order they were written. Everything after the last frame is kept in the
+// restore data.
+pub(crate) fn write_trailer(
+  out: &mut dyn Write,
+  original: &[u8],
+  frames: impl ExactSizeIterator<Item = Range<usize>>,
+) -> std::io::Result<()> {
+  let mut data = Vec::with_capacity(48 + frames.len() * 16 + 512);
+  data.extend_from_slice(RESTORE_MAGIC);
+  data.extend_from_slice(&RESTORE_VERSION.to_le_bytes());
+  data.extend_from_slice(&(original.len() as u64).to_le_bytes());
+  data.extend_from_slice(&(frames.len() as u64).to_le_bytes());
+  let mut crushed_len = 0u64;
+  let mut frames_end = 0;
+  for bounds in frames {
+    data.extend_from_slice(&(bounds.start as u64).to_le_bytes());
+    data.extend_from_slice(&(bounds.len() as u64).to_le_bytes());
+    crushed_len += bounds.len() as u64;
+    frames_end = frames_end.max(bounds.end);
+  }
+  let trailing = &original[frames_end..];
+  let start = trailing.iter().position(|&b| b != 0).unwrap_or(trailing.len());
+  let end = trailing.iter().rposition(|&b| b != 0).map_or(start, |i| i + 1);
+  data.extend_from_slice(&((frames_end + start) as u64).to_le_bytes());
+  data.extend_from_slice(&((end - start) as u64).to_le_bytes());
+  data.extend_from_slice(&trailing[start..end]);
+  data.resize((data.len() + FOOTER_LEN).next_multiple_of(512) - FOOTER_LEN, 0);
+  data.extend_from_slice(&crushed_len.to_le_bytes());
+  data.extend_from_slice(RESTORE_MAGIC);
+  out.write_all(&tar::END_OF_ARCHIVE)?;
+  out.write_all(&tar::build_header(RESTORE_MEMBER_NAME, b'0', data.len() as u64))?;
+  out.write_all(&data)
+}
+
+fn bad_restore_data(offset: usize, msg: &'static str) -> Error {
+  Error::malformed(offset, Malformation::BadRestoreData(msg))
+}
+
+fn read_u64(input: &[u8], offset: usize) -> Result<u64, Error> {
+  match input.get(offset..offset + 8) {
+    Some(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+    None => Err(bad_restore_data(input.len(), "truncated restore data")),
+  }
+}
+
+fn read_usize(input: &[u8], offset: usize) -> Result<usize, Error> {
+  usize::try_from(read_u64(input, offset)?)
+    .map_err(|_| bad_restore_data(offset, "restore data field out of range"))
+}
+
+// Finds the restore data in a crushed archive, returning the length of the
+// crushed frames before it and the offset of the data itself.
+fn locate_restore_data(crushed: &[u8]) -> Result<(usize, usize), Error> {
+  let not_crushed = || bad_restore_data(crushed.len(), "no restore data found; was this archive crushed?");
+  if crushed.len() < FOOTER_LEN || !crushed.ends_with(RESTORE_MAGIC) {
+    return Err(not_crushed());
+  }
+  let crushed_len = read_usize(crushed, crushed.len() - FOOTER_LEN)?;
+  let header_offset = crushed_len
+    .checked_add(tar::END_OF_ARCHIVE.len())
+    .filter(|&offset| crushed.len().checked_sub(512).is_some_and(|last| offset <= last))
+    .ok_or_else(not_crushed)?;
+  let header: &[u8; 512] = crushed[header_offset..header_offset + 512].try_into().unwrap();
+  let header = tar::Header(header);
+  if header.name() != RESTORE_MEMBER_NAME {
+    return Err(bad_restore_data(header_offset, "restore data header not found"));
+  }
+  let data_offset = header_offset + 512;
+  let data = &crushed[data_offset..];
+  if header.content_len().ok() != Some(data.len() as u64) || !data.starts_with(RESTORE_MAGIC) {
+    return Err(bad_restore_data(data_offset, "malformed restore data"));
+  }
+  Ok((crushed_len, data_offset))
+}
+
+// The length of the reordered frames at the start of a crushed archive.
+pub(crate) fn crushed_len(crushed: &[u8]) -> Result<usize, Error> {
+  locate_restore_data(crushed).map(|(crushed_len, _)| crushed_len)
+}
+
+// Writes the original archive that the crushed archive was produced from.
+pub fn restore(crushed: &[u8], out: &mut dyn Write) -> Result<(), Error> {
+  let (crushed_len, data_offset) = locate_restore_data(crushed)?;
+  let data = &crushed[data_offset..];
+  if read_u64(data, 8)? != RESTORE_VERSION {
+    return Err(bad_restore_data(data_offset + 8, "unsupported restore data version"));
+  }
+  let original_len = read_usize(data, 16)?;
+  let n_frames = read_usize(data, 24)?;
+
+  // Pair up each frame's location in the crushed archive with its location in the original.
+  let mut frames = Vec::with_capacity(n_frames.min(data.len() / 16));
+  let mut crushed_offset = 0usize;
+  for i in 0..n_frames {
+    let entry_offset = 32 + i * 16;
+    let original_start = read_usize(data, entry_offset)?;
+    let len = read_usize(data, entry_offset + 8)?;
+    let original = original_start..original_start.saturating_add(len);
+    let crushed = crushed_offset..crushed_offset.saturating_add(len);
+    if original.end > original_len || crushed.end > crushed_len {
+      return Err(bad_restore_data(data_offset + entry_offset, "frame out of bounds"));
+    }
+    crushed_offset = crushed.end;
+    frames.push((original, crushed));
+  }
+  if crushed_offset != crushed_len {
+    return Err(bad_restore_data(data_offset, "frames do not cover the crushed archive"));
+  }
+  // The trailing bytes are restored just like a frame, but from within the
+  // restore data.
+  let trailing_offset = 32 + n_frames * 16;
+  let original_start = read_usize(data, trailing_offset)?;
+  let len = read_usize(data, trailing_offset + 8)?;
+  let original = original_start..original_start.saturating_add(len);
+  let bytes_offset = data_offset + trailing_offset + 16;
+  let bytes = bytes_offset..bytes_offset.saturating_add(len);
+  if original.end > original_len || bytes.end > crushed.len() {
+    return Err(bad_restore_data(data_offset + trailing_offset, "trailing bytes out of bounds"));
+  }
+  frames.push((original, bytes));
+
+  frames.sort_by_key(|(original, _)| original.start);
+  let mut original_offset = 0;
+  for (original, crushed_bounds) in frames {
+    if original.start < original_offset {
+      return Err(bad_restore_data(data_offset, "frames overlap"));
+    }
+    write_zeroes(out, original.start - original_offset).map_err(Error::EgressIO)?;
+    out.write_all(&crushed[crushed_bounds]).map_err(Error::EgressIO)?;
+    original_offset = original.end;
+  }
+  write_zeroes(out, original_len - original_offset).map_err(Error::EgressIO)?;
+  out.flush().map_err(Error::EgressIO)
+}
+
+fn write_zeroes(out: &mut dyn Write, mut len: usize) -> std::io::Result<()> {
+  const ZEROES: [u8; 4096] = [0; 4096];
+  while len > 0 {
+    let chunk = len.min(ZEROES.len());
+    out.write_all(&ZEROES[..chunk])?;
+    len -= chunk;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crush::crush;
+  use crate::params::Params;
+  use crate::ingress::MapStrategy;
+  use crate::util::testing::{member, random_bytes, END_OF_ARCHIVE};
+
+  fn round_trip(original: &[u8]) -> Vec<u8> {
+    let mut crushed = Vec::new();
+    crush(&mut MapStrategy::new(original), &mut crushed, &Params::default()).unwrap();
+    let mut restored = Vec::new();
+    restore(&crushed, &mut restored).unwrap();
+    restored
+  }
+
+  fn archive() -> Vec<u8> {
+    [
+      member(b"a.txt", b'0', b"The quick brown fox jumps over the lazy dog."),
+      member(b"b.bin", b'0', &random_bytes(1, 100_000)),
+      member(b"././@LongLink", b'L', b"x/long/name"),
+      member(b"x/long/nam", b'0', b""),
+      member(b"c.txt", b'0', b"The quick brown fox jumps over the lazy cat."),
+      member(b"d.bin", b'0', &random_bytes(2, 5000)),
+      END_OF_ARCHIVE.to_vec(),
+    ]
+    .concat()
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let original = archive();
+    assert_eq!(round_trip(&original), original);
+  }
+
+  #[test]
+  fn test_round_trip_padding() {
+    let mut original = member(b"a.txt", b'0', b"hello");
+    original.resize(10240, 0);
+    assert_eq!(round_trip(&original), original);
+    let mut original = vec![0; 512];
+    original.extend_from_slice(&member(b"a.txt", b'0', b"hello"));
+    assert_eq!(round_trip(&original), original);
+  }
+
+  #[test]
+  fn test_round_trip_after_end_of_archive() {
+    // Whatever follows the first zero block must be recreated, including
+    // members, zero blocks between them and partial blocks.
+    let mut original = archive();
+    original.extend_from_slice(&member(b"e.txt", b'0', b"hello"));
+    original.extend_from_slice(&[0; 512]);
+    original.extend_from_slice(&member(b"f.txt", b'0', b"world"));
+    original.extend_from_slice(b"garbage");
+    original.resize(original.len() + 1000, 0);
+    assert_eq!(round_trip(&original), original);
+    // Including the trailer of an archive that has already been crushed.
+    let mut crushed = Vec::new();
+    crush(&mut MapStrategy::new(&original), &mut crushed, &Params::default()).unwrap();
+    assert_eq!(round_trip(&crushed), crushed);
+  }
+
+  #[test]
+  fn test_round_trip_empty() {
+    assert_eq!(round_trip(b""), b"");
+    assert_eq!(round_trip(&END_OF_ARCHIVE), END_OF_ARCHIVE);
+  }
+
+  #[test]
+  fn test_crushed_is_valid_tar() {
+    // The restore data must follow an end-of-archive marker, and the
+    // reordered frames must all be within the part of the archive that tar
+    // implementations read.
+    let original = archive();
+    let mut crushed = Vec::new();
+    crush(&mut MapStrategy::new(&original), &mut crushed, &Params::default()).unwrap();
+    let eoa = original.len() - END_OF_ARCHIVE.len();
+    assert_eq!(&crushed[eoa..eoa + END_OF_ARCHIVE.len()], END_OF_ARCHIVE);
+    assert_eq!(tar::Header(crushed[eoa + 1024..eoa + 1536].try_into().unwrap()).name(), RESTORE_MEMBER_NAME);
+    assert_eq!(crushed.len() % 512, 0);
+  }
+
+  #[test]
+  fn test_not_crushed() {
+    let result = restore(&archive(), &mut Vec::new());
+    assert!(matches!(result, Err(Error::MalformedInput(..))));
+    assert!(matches!(restore(b"", &mut Vec::new()), Err(Error::MalformedInput(..))));
+    assert!(matches!(restore(b"xxxxxxxxTARCRUSH", &mut Vec::new()), Err(Error::MalformedInput(..))));
+  }
+
+  #[test]
+  fn test_corrupt_restore_data() {
+    let mut crushed = Vec::new();
+    crush(&mut MapStrategy::new(&archive()), &mut crushed, &Params::default()).unwrap();
+    let data_offset = crushed.len() - 512;
+    // Make the first frame overrun the crushed archive.
+    let mut corrupt = crushed.clone();
+    corrupt[data_offset + 40..data_offset + 48].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(matches!(restore(&corrupt, &mut Vec::new()), Err(Error::MalformedInput(..))));
+    // Point the footer somewhere nonsensical.
+    let mut corrupt = crushed.clone();
+    let footer_offset = crushed.len() - FOOTER_LEN;
+    corrupt[footer_offset..footer_offset + 8].copy_from_slice(&12345u64.to_le_bytes());
+    assert!(matches!(restore(&corrupt, &mut Vec::new()), Err(Error::MalformedInput(..))));
+  }
+}
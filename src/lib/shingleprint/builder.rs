@@ -0,0 +1,150 @@
+use super::hash::{self, ShingleHash, ShingleHasher};
+use super::{shingle_hashes_specialised, ShingleHashesConsumer, Shingleprint, ShingleprintConfig};
+use crate::tunables::{MAX_SHINGLEPRINT_FEATURES, MAX_SHINGLE_LEN};
+use crate::util::k_smallest_unique::k_smallest_unique;
+use arrayvec::ArrayVec;
+
+// Shingleprints input supplied in chunks, for when it isn't all in memory at
+// once. The result is the same as shingleprinting the concatenated chunks.
+#[derive(Clone, Debug)]
+pub struct ShingleprintBuilder {
+  config: ShingleprintConfig,
+  // The last shingle_len - 1 bytes of input so far (or all of it, if there
+  // has been less), which begin shingles that end in the next chunk.
+  carry: ArrayVec<u8, MAX_SHINGLE_LEN>,
+  // Invariant: sorted in ascending order, as in a Shingleprint.
+  hashes: ArrayVec<ShingleHash, MAX_SHINGLEPRINT_FEATURES>,
+}
+
+impl ShingleprintBuilder {
+  pub fn new(config: &ShingleprintConfig) -> Self {
+    Self { config: *config, carry: ArrayVec::new(), hashes: ArrayVec::new() }
+  }
+
+  #[cfg(target_arch = "x86_64")]
+  pub fn update(&mut self, chunk: &[u8]) {
+    if is_x86_feature_detected!("sse4.2") {
+      unsafe { self.update_sse(chunk) }
+    } else {
+      self.update_with::<hash::RollingCrc32c>(chunk)
+    }
+  }
+
+  #[cfg(target_arch = "aarch64")]
+  pub fn update(&mut self, chunk: &[u8]) {
+    if std::arch::is_aarch64_feature_detected!("crc") {
+      unsafe { self.update_crc(chunk) }
+    } else {
+      self.update_with::<hash::RollingCrc32c>(chunk)
+    }
+  }
+
+  #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+  pub fn update(&mut self, chunk: &[u8]) {
+    self.update_with::<hash::RollingCrc32c>(chunk)
+  }
+
+  // Undefined behaviour if the processor doesn't support the sse4.2 feature.
+  #[cfg(target_arch = "x86_64")]
+  #[target_feature(enable = "sse4.2")]
+  unsafe fn update_sse(&mut self, chunk: &[u8]) {
+    self.update_with::<hash::Crc32cSse>(chunk)
+  }
+
+  // Undefined behaviour if the processor doesn't support the crc feature.
+  #[cfg(target_arch = "aarch64")]
+  #[target_feature(enable = "crc")]
+  unsafe fn update_crc(&mut self, chunk: &[u8]) {
+    self.update_with::<hash::Crc32cArm>(chunk)
+  }
+
+  #[inline(always)]
+  fn update_with<H: ShingleHasher<Hash = ShingleHash>>(&mut self, chunk: &[u8]) {
+    let shingle_len = self.config.shingle_len();
+    let overlap = shingle_len - 1;
+    // The shingles straddling the boundary are those of the carried-over
+    // bytes followed by the start of the chunk. None fit in the chunk's part
+    // alone, which is shorter than a shingle.
+    let mut joined: ArrayVec<u8, { 2 * MAX_SHINGLE_LEN }> = self.carry.iter().copied().collect();
+    joined.try_extend_from_slice(&chunk[..chunk.len().min(overlap)]).unwrap();
+    // The bottom-k of a union is the bottom-k of the union of the parts' bottom-ks.
+    let hashes = self.hashes.iter().copied().chain(H::shingle_hashes(&joined, shingle_len));
+    let features = self.config.features();
+    self.hashes = shingle_hashes_specialised::<H, _>(chunk, shingle_len, KSmallestUniqueWith(hashes, features));
+
+    self.carry.clear();
+    if chunk.len() >= overlap {
+      self.carry.try_extend_from_slice(&chunk[chunk.len() - overlap..]).unwrap();
+    } else {
+      // joined holds the whole chunk.
+      self.carry.try_extend_from_slice(&joined[joined.len().saturating_sub(overlap)..]).unwrap();
+    }
+  }
+
+  pub fn finish(self) -> Shingleprint {
+    Shingleprint::new(self.hashes, &self.config)
+  }
+}
+
+// Takes the k smallest unique hashes, along with some others.
+struct KSmallestUniqueWith<I>(I, usize);
+
+impl<I: Iterator<Item = ShingleHash>> ShingleHashesConsumer<ShingleHash> for KSmallestUniqueWith<I> {
+  type Output = ArrayVec<ShingleHash, MAX_SHINGLEPRINT_FEATURES>;
+  #[inline(always)]
+  fn consume(self, hashes: impl Iterator<Item = ShingleHash>) -> Self::Output {
+    k_smallest_unique(self.0.chain(hashes), self.1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shingleprint::{shingleprint, shingleprint_portable};
+  use crate::util::testing::random_bytes;
+
+  fn build(config: &ShingleprintConfig, chunks: &[&[u8]]) -> Shingleprint {
+    let mut builder = ShingleprintBuilder::new(config);
+    for chunk in chunks {
+      builder.update(chunk);
+    }
+    builder.finish()
+  }
+
+  #[test]
+  fn test_same_as_one_shot() {
+    let input = random_bytes(1, 5000);
+    for (shingle_len, features) in [(1, 16), (16, 32), (7, 48), (MAX_SHINGLE_LEN, MAX_SHINGLEPRINT_FEATURES)] {
+      let config = ShingleprintConfig::new(shingle_len, features).unwrap();
+      let expected = shingleprint(&input, &config);
+      assert_eq!(build(&config, &[&input]), expected);
+      // Chunks of assorted sizes, including empty ones and ones shorter
+      // than a shingle, so that shingles straddle several chunks.
+      for chunk_len in [1, 2, shingle_len.saturating_sub(1).max(1), shingle_len, 100, 4999] {
+        let chunks: Vec<_> = input.chunks(chunk_len).collect();
+        assert_eq!(build(&config, &chunks), expected, "shingle_len {shingle_len}, chunk_len {chunk_len}");
+      }
+      let (a, b) = input.split_at(shingle_len / 2);
+      assert_eq!(build(&config, &[b"", a, b"", b, b""]), expected);
+    }
+  }
+
+  #[test]
+  fn test_portable_fallback() {
+    let config = ShingleprintConfig::default();
+    let input = random_bytes(2, 3000);
+    let mut builder = ShingleprintBuilder::new(&config);
+    for chunk in input.chunks(37) {
+      builder.update_with::<hash::RollingCrc32c>(chunk);
+    }
+    assert_eq!(builder.finish(), shingleprint_portable(&input, &config));
+  }
+
+  #[test]
+  fn test_short_input() {
+    let config = ShingleprintConfig::default();
+    assert_eq!(build(&config, &[]), shingleprint(b"", &config));
+    assert_eq!(build(&config, &[b"too", b"short"]), shingleprint(b"tooshort", &config));
+    assert_eq!(build(&config, &[b"just long", b" enough!"]), shingleprint(b"just long enough!", &config));
+  }
+}
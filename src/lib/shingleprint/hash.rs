@@ -1,15 +1,27 @@
-// CRC-32 implementation.
+// Shingle hash functions. CRC-32C is the default, being fast with hardware
+// support and well distributed enough for shingleprinting.
+
+use crate::tunables::MAX_SHINGLE_LEN;
+use std::sync::OnceLock;
 
 pub type ShingleHash = u32;
 
+// Computes the hash of each shingle_len-byte window of an input, in order.
+pub trait ShingleHasher {
+  type Hash: Copy + Ord;
+  fn shingle_hashes(input: &[u8], shingle_len: usize) -> impl Iterator<Item = Self::Hash> + '_;
+}
+
+// CRC-32C implementation.
+
 // The least significant bit of the first byte of the message is considered to
 // be the coefficient of the highest-power term in the message polynomial.
 
 // The least significant bit of DIVISOR is the coefficient of x^31 in the
 // divisor polynomial, and the most significant bit is the coefficient of x^0.
 // The coefficient of x^32 is implied to be 1.
-// This divisor is chosen to be the same as that used by x86_64 SSE4.2
-// hardware acceleration.
+// This divisor is chosen to be the same as that used by x86_64 SSE4.2 and
+// ARMv8 CRC extension hardware acceleration.
 const DIVISOR: u32 = 0x82F63B78;
 
 // N is the number of entries in the lookup table, which must be a power of 2.
@@ -34,7 +46,12 @@ const fn generate_lut<const N: usize>() -> [u32; N] {
   lut
 }
 const LUT8: [u32; 256] = generate_lut::<256>();
-const LUT16: [u32; 65536] = generate_lut::<65536>();
+static LUT16: [u32; 65536] = generate_lut::<65536>();
+
+// Advances a CRC register over one byte of the message.
+const fn step(accum: u32, byte: u8) -> u32 {
+  LUT8[((accum as u8) ^ byte) as usize] ^ (accum >> 8)
+}
 
 pub fn hash_portable(input: &[u8]) -> ShingleHash {
   let mut accum = u32::MAX;
@@ -44,12 +61,13 @@ pub fn hash_portable(input: &[u8]) -> ShingleHash {
     accum = LUT16[((accum as u16) ^ chunk) as usize] ^ (accum >> 16);
   }
   for &byte in chunks.remainder() {
-    accum = LUT8[((accum as u8) ^ byte) as usize] ^ (accum >> 8);
+    accum = step(accum, byte);
   }
   !accum
 }
 
 // Undefined behaviour if the processor doesn't support the sse4.2 feature.
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "sse4.2")]
 pub unsafe fn hash_sse(input: &[u8]) -> ShingleHash {
   let mut accum = u64::MAX; // upper 32 bits are ignored throughout.
@@ -65,14 +83,152 @@ pub unsafe fn hash_sse(input: &[u8]) -> ShingleHash {
   !accum
 }
 
+// Undefined behaviour if the processor doesn't support the crc feature.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+pub unsafe fn hash_crc(input: &[u8]) -> ShingleHash {
+  let mut accum = u32::MAX;
+  let mut chunks = input.chunks_exact(8); // of 64 bits
+  for chunk in chunks.by_ref() {
+    let chunk = u64::from_le_bytes(chunk.try_into().unwrap());
+    accum = core::arch::aarch64::__crc32cd(accum, chunk);
+  }
+  for &byte in chunks.remainder() {
+    accum = core::arch::aarch64::__crc32cb(accum, byte);
+  }
+  !accum
+}
+
+// Panics if the processor doesn't support the sse4.2 feature.
+#[cfg(target_arch = "x86_64")]
+pub struct Crc32cSse;
+
+#[cfg(target_arch = "x86_64")]
+impl ShingleHasher for Crc32cSse {
+  type Hash = ShingleHash;
+  #[inline]
+  fn shingle_hashes(input: &[u8], shingle_len: usize) -> impl Iterator<Item = ShingleHash> + '_ {
+    assert!(is_x86_feature_detected!("sse4.2"), "sse4.2 not supported");
+    input.windows(shingle_len).map(|s| unsafe { hash_sse(s) })
+  }
+}
+
+// Panics if the processor doesn't support the crc feature.
+#[cfg(target_arch = "aarch64")]
+pub struct Crc32cArm;
+
+#[cfg(target_arch = "aarch64")]
+impl ShingleHasher for Crc32cArm {
+  type Hash = ShingleHash;
+  #[inline]
+  fn shingle_hashes(input: &[u8], shingle_len: usize) -> impl Iterator<Item = ShingleHash> + '_ {
+    assert!(std::arch::is_aarch64_feature_detected!("crc"), "crc not supported");
+    input.windows(shingle_len).map(|s| unsafe { hash_crc(s) })
+  }
+}
+
+pub struct Crc32cPortable;
+
+impl ShingleHasher for Crc32cPortable {
+  type Hash = ShingleHash;
+  fn shingle_hashes(input: &[u8], shingle_len: usize) -> impl Iterator<Item = ShingleHash> + '_ {
+    input.windows(shingle_len).map(hash_portable)
+  }
+}
+
+// CRC is linear: the register after processing a message from an initial
+// value of zero is the XOR of the registers for each byte of the message in
+// its place, with zeroes elsewhere, and leading zeroes don't affect it. So a
+// window's first byte can be removed by XORing in that byte's contribution,
+// leaving the register for the rest of the window, ready to take another byte.
+// The initial value of all ones contributes a constant for a given length.
+
+struct RollingTables {
+  // remove[b] is the register for b followed by shingle_len - 1 zero bytes.
+  remove: [u32; 256],
+  // The contribution of the initial value to a shingle_len-byte message.
+  init: u32,
+}
+
+impl RollingTables {
+  fn new(shingle_len: usize) -> Self {
+    let zeroes = |accum| (1..shingle_len).fold(accum, |accum, _| step(accum, 0));
+    Self {
+      remove: std::array::from_fn(|byte| zeroes(step(0, byte as u8))),
+      init: step(zeroes(u32::MAX), 0),
+    }
+  }
+
+  // Tables are built on first use for each shingle length.
+  fn get(shingle_len: usize) -> &'static Self {
+    static TABLES: [OnceLock<RollingTables>; MAX_SHINGLE_LEN + 1] = [const { OnceLock::new() }; MAX_SHINGLE_LEN + 1];
+    TABLES[shingle_len].get_or_init(|| Self::new(shingle_len))
+  }
+}
+
+// Produces the same hashes as Crc32cPortable, but updates the hash a byte at
+// a time as the window slides, rather than rehashing each window in full.
+// Panics if shingle_len is zero or exceeds MAX_SHINGLE_LEN.
+pub struct RollingCrc32c;
+
+impl ShingleHasher for RollingCrc32c {
+  type Hash = ShingleHash;
+  fn shingle_hashes(input: &[u8], shingle_len: usize) -> impl Iterator<Item = ShingleHash> + '_ {
+    assert!(shingle_len > 0);
+    let tables = RollingTables::get(shingle_len);
+    let (first, accum) = match input.get(..shingle_len) {
+      Some(window) => (true, window.iter().fold(0, |accum, &byte| step(accum, byte))),
+      None => (false, 0),
+    };
+    let incoming = input.get(shingle_len..).unwrap_or_default();
+    RollingHashes { bytes: incoming.iter().zip(input), tables, first, accum }
+  }
+}
+
+struct RollingHashes<'a> {
+  // Pairs of bytes entering and leaving the window as it slides.
+  bytes: std::iter::Zip<std::slice::Iter<'a, u8>, std::slice::Iter<'a, u8>>,
+  tables: &'static RollingTables,
+  // Whether the first window, whose register is already in accum, is yet to be yielded.
+  first: bool,
+  accum: u32,
+}
+
+impl<'a> Iterator for RollingHashes<'a> {
+  type Item = ShingleHash;
+
+  #[inline]
+  fn next(&mut self) -> Option<ShingleHash> {
+    if self.first {
+      self.first = false;
+    } else {
+      let (&byte_in, &byte_out) = self.bytes.next()?;
+      self.accum = step(self.accum ^ self.tables.remove[byte_out as usize], byte_in);
+    }
+    Some(!(self.accum ^ self.tables.init))
+  }
+}
+
+// A 64-bit hash, making collisions between distinct shingles vanishingly
+// unlikely even in very large archives, at the cost of speed and of
+// shingleprints twice the size.
+pub struct Xxh3;
+
+impl ShingleHasher for Xxh3 {
+  type Hash = u64;
+  fn shingle_hashes(input: &[u8], shingle_len: usize) -> impl Iterator<Item = u64> + '_ {
+    input.windows(shingle_len).map(xxhash_rust::xxh3::xxh3_64)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
-  const INPUT1: &'static [u8] = b"MOUNTAINAARDVARK";
+  const INPUT1: &[u8] = b"MOUNTAINAARDVARK";
   const EXPECTED_OUTPUT1: ShingleHash = 0xEC8D5402;
 
-  const INPUT2: &'static [u8] = b"Absentmindedness";
+  const INPUT2: &[u8] = b"Absentmindedness";
   const EXPECTED_OUTPUT2: ShingleHash = 0x25066ADF;
 
   #[test]
@@ -81,11 +237,48 @@ mod tests {
     assert_eq!(hash_portable(INPUT2), EXPECTED_OUTPUT2);
   }
 
+  #[test]
+  fn test_rolling() {
+    let hashes: Vec<_> = RollingCrc32c::shingle_hashes(INPUT1, 16).collect();
+    assert_eq!(hashes, [EXPECTED_OUTPUT1]);
+    let input = [INPUT1, INPUT2, b"and a few more bytes"].concat();
+    for shingle_len in [1, 7, 16, 33] {
+      for len in 0..=input.len() {
+        let expected: Vec<_> = Crc32cPortable::shingle_hashes(&input[..len], shingle_len).collect();
+        let got: Vec<_> = RollingCrc32c::shingle_hashes(&input[..len], shingle_len).collect();
+        assert_eq!(got, expected, "shingle_len {shingle_len}, len {len}");
+      }
+    }
+  }
+
+  #[cfg(target_arch = "x86_64")]
   #[test]
   fn test_sse() {
     if is_x86_feature_detected!("sse4.2") {
       assert_eq!(unsafe { hash_sse(INPUT1) }, EXPECTED_OUTPUT1);
       assert_eq!(unsafe { hash_sse(INPUT2) }, EXPECTED_OUTPUT2);
+      let input = [INPUT1, INPUT2].concat();
+      assert!(Crc32cSse::shingle_hashes(&input, 11).eq(Crc32cPortable::shingle_hashes(&input, 11)));
+    }
+  }
+
+  #[test]
+  fn test_xxh3() {
+    let input = [INPUT1, INPUT2].concat();
+    let hashes: Vec<_> = Xxh3::shingle_hashes(&input, 16).collect();
+    assert_eq!(hashes.len(), input.len() - 16 + 1);
+    assert_eq!(hashes[0], xxhash_rust::xxh3::xxh3_64(INPUT1));
+    assert_eq!(hashes[16], xxhash_rust::xxh3::xxh3_64(INPUT2));
+  }
+
+  #[cfg(target_arch = "aarch64")]
+  #[test]
+  fn test_crc() {
+    if std::arch::is_aarch64_feature_detected!("crc") {
+      assert_eq!(unsafe { hash_crc(INPUT1) }, EXPECTED_OUTPUT1);
+      assert_eq!(unsafe { hash_crc(INPUT2) }, EXPECTED_OUTPUT2);
+      let input = [INPUT1, INPUT2].concat();
+      assert!(Crc32cArm::shingle_hashes(&input, 11).eq(Crc32cPortable::shingle_hashes(&input, 11)));
     }
   }
 }
@@ -0,0 +1,142 @@
+// Locality-sensitive hashing index over shingleprints.
+//
+// Classic LSH banding splits a MinHash signature positionally into bands of
+// rows. A bottom-k shingleprint has no fixed positions (one extra small hash
+// shifts every later element along), so instead each hash is assigned to a
+// band by its value, modulo LSH_BANDS. A band's key is the LSH_ROWS smallest
+// hashes assigned to it, which is itself a bottom-k sample of the shingles
+// falling into that band, so two shingleprints agree on a band with
+// probability roughly J^LSH_ROWS for Jaccard similarity J.
+
+use super::hash::ShingleHash;
+use super::Shingleprint;
+use crate::tunables::{LSH_BANDS, LSH_MAX_BUCKET_SCAN, LSH_ROWS};
+use arrayvec::ArrayVec;
+use std::collections::HashMap;
+
+type BandKey = (usize, ArrayVec<ShingleHash, LSH_ROWS>);
+
+fn band_keys(sp: &Shingleprint) -> impl Iterator<Item = BandKey> + '_ {
+  // If the shingleprint holds every shingle hash of its input, short bands
+  // are complete rather than missing elements.
+  let complete = sp.is_complete();
+  (0..LSH_BANDS).filter_map(move |band| {
+    let rows: ArrayVec<_, LSH_ROWS> = sp
+      .hashes()
+      .iter()
+      .copied()
+      .filter(|&hash| hash as usize % LSH_BANDS == band)
+      .take(LSH_ROWS)
+      .collect();
+    if rows.is_full() || (complete && !rows.is_empty()) {
+      Some((band, rows))
+    } else {
+      None
+    }
+  })
+}
+
+#[derive(Debug, Default)]
+pub struct LshIndex {
+  // Invariant: ids within each bucket are in ascending order.
+  buckets: HashMap<BandKey, Vec<usize>>,
+}
+
+impl LshIndex {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // Ids must be inserted in ascending order.
+  pub fn insert(&mut self, id: usize, sp: &Shingleprint) {
+    for key in band_keys(sp) {
+      let bucket = self.buckets.entry(key).or_default();
+      debug_assert!(bucket.last().is_none_or(|&last| last < id));
+      bucket.push(id);
+    }
+  }
+
+  // Returns the ids of indexed shingleprints that share at least one band
+  // with sp, in ascending order and without duplicates.
+  // Buckets holding more than LSH_MAX_BUCKET_SCAN ids (content common to a
+  // large part of the archive) are only scanned in the vicinity of near, so
+  // that the cost of a query is bounded.
+  pub fn candidates(&self, sp: &Shingleprint, near: usize) -> Vec<usize> {
+    let mut candidates = Vec::new();
+    for key in band_keys(sp) {
+      let Some(bucket) = self.buckets.get(&key) else { continue };
+      if bucket.len() <= LSH_MAX_BUCKET_SCAN {
+        candidates.extend_from_slice(bucket);
+      } else {
+        let centre = bucket.partition_point(|&id| id < near);
+        let start = centre.saturating_sub(LSH_MAX_BUCKET_SCAN / 2).min(bucket.len() - LSH_MAX_BUCKET_SCAN);
+        candidates.extend_from_slice(&bucket[start..start + LSH_MAX_BUCKET_SCAN]);
+      }
+    }
+    candidates.sort_unstable();
+    candidates.dedup();
+    candidates
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shingleprint::shingleprint_portable;
+  use crate::util::testing::random_bytes;
+
+  fn mutate(input: &[u8], seed: u64, n: usize) -> Vec<u8> {
+    let mut output = input.to_vec();
+    let positions = random_bytes(seed, n * 8);
+    for position in positions.chunks_exact(8) {
+      let position = u64::from_le_bytes(position.try_into().unwrap()) as usize % output.len();
+      output[position] ^= 0x55;
+    }
+    output
+  }
+
+  #[test]
+  fn test_finds_near_duplicates() {
+    let originals: Vec<_> = (0..100).map(|i| random_bytes(2 * i + 1, 4096)).collect();
+    let mut index = LshIndex::new();
+    for (id, original) in originals.iter().enumerate() {
+      index.insert(id, &shingleprint_portable(original, &Default::default()));
+    }
+    let mut found = 0;
+    for (id, original) in originals.iter().enumerate() {
+      // A handful of edits leaves the Jaccard similarity around 0.9.
+      let query = shingleprint_portable(&mutate(original, 1000 + id as u64, 10), &Default::default());
+      let candidates = index.candidates(&query, id);
+      if candidates.contains(&id) {
+        found += 1;
+      }
+      // Unrelated inputs share no shingles, so should never be candidates.
+      assert!(candidates.len() <= 1, "{candidates:?}");
+    }
+    assert!(found >= 95, "only found {found} of 100 near-duplicates");
+  }
+
+  #[test]
+  fn test_short_inputs() {
+    let mut index = LshIndex::new();
+    index.insert(0, &shingleprint_portable(b"a short input, with few shingles", &Default::default()));
+    index.insert(1, &shingleprint_portable(b"something entirely unrelated", &Default::default()));
+    assert_eq!(index.candidates(&shingleprint_portable(b"a short input, with few shingles", &Default::default()), 0), [0]);
+    assert!(index.candidates(&shingleprint_portable(b"", &Default::default()), 0).is_empty());
+  }
+
+  #[test]
+  fn test_large_bucket_scanned_near_query() {
+    let sp = shingleprint_portable(&random_bytes(1, 1000), &Default::default());
+    let mut index = LshIndex::new();
+    let n = LSH_MAX_BUCKET_SCAN * 4;
+    for id in 0..n {
+      index.insert(id, &sp);
+    }
+    let candidates = index.candidates(&sp, n / 2);
+    assert_eq!(candidates.len(), LSH_MAX_BUCKET_SCAN);
+    assert!(candidates.contains(&(n / 2)));
+    assert_eq!(index.candidates(&sp, 0), (0..LSH_MAX_BUCKET_SCAN).collect::<Vec<_>>());
+    assert_eq!(index.candidates(&sp, n), (n - LSH_MAX_BUCKET_SCAN..n).collect::<Vec<_>>());
+  }
+}
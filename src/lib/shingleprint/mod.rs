@@ -1,41 +1,347 @@
+use crate::tunables::{MAX_SHINGLEPRINT_FEATURES, MAX_SHINGLE_LEN, MIN_SHINGLEPRINT_FEATURES};
 use crate::util::k_smallest_unique::k_smallest_unique;
+#[cfg(target_arch = "x86_64")]
+use crate::util::k_smallest_unique_avx2::k_smallest_unique_avx2;
 use arrayvec::ArrayVec;
+use std::cmp::Ordering;
+use std::fmt;
 
 pub use crate::tunables::{SHINGLEPRINT_FEATURES, SHINGLE_LEN};
 
+mod builder;
 pub mod hash;
+pub mod lsh;
+
+pub use builder::ShingleprintBuilder;
+
+// How inputs are shingleprinted. Shorter shingles suit text, where short
+// phrases recur in different contexts; longer ones suit binary data. More
+// features make similarity estimates more accurate, at the cost of memory
+// and time spent comparing frames.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ShingleprintConfig {
+  shingle_len: usize,
+  features: usize,
+}
+
+impl ShingleprintConfig {
+  pub const DEFAULT: Self = Self { shingle_len: SHINGLE_LEN, features: SHINGLEPRINT_FEATURES };
+
+  pub fn new(shingle_len: usize, features: usize) -> Result<Self, ConfigError> {
+    if !(1..=MAX_SHINGLE_LEN).contains(&shingle_len) {
+      return Err(ConfigError::ShingleLen(shingle_len));
+    }
+    if !(MIN_SHINGLEPRINT_FEATURES..=MAX_SHINGLEPRINT_FEATURES).contains(&features) {
+      return Err(ConfigError::Features(features));
+    }
+    Ok(Self { shingle_len, features })
+  }
+
+  // Length in bytes of the windows that are hashed.
+  pub fn shingle_len(&self) -> usize {
+    self.shingle_len
+  }
+
+  // Number of smallest hashes retained in each shingleprint.
+  pub fn features(&self) -> usize {
+    self.features
+  }
+}
+
+impl Default for ShingleprintConfig {
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
 
-// Invariant: array elements are sorted in ascending order.
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Shingleprint(ArrayVec<hash::ShingleHash, SHINGLEPRINT_FEATURES>);
+pub enum ConfigError {
+  ShingleLen(usize),
+  Features(usize),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConfigError::ShingleLen(len) => write!(f, "shingle length must be between 1 and {MAX_SHINGLE_LEN}, not {len}"),
+      ConfigError::Features(features) => write!(
+        f,
+        "feature count must be between {MIN_SHINGLEPRINT_FEATURES} and {MAX_SHINGLEPRINT_FEATURES}, not {features}",
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Invariant: hashes are sorted in ascending order, and there are at most
+// features of them; fewer only if the input had fewer distinct shingles.
+// Ordering is lexicographic, so sorting by shingleprint brings together
+// shingleprints that share their smallest hashes.
+// Elements are hashes from the default ShingleHasher unless otherwise stated.
+// Shingleprints can only be compared with those made with the same config.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Shingleprint<T = hash::ShingleHash> {
+  hashes: ArrayVec<T, MAX_SHINGLEPRINT_FEATURES>,
+  features: u8,
+}
+
+impl<T> Shingleprint<T> {
+  fn new(hashes: ArrayVec<T, MAX_SHINGLEPRINT_FEATURES>, config: &ShingleprintConfig) -> Self {
+    debug_assert!(hashes.len() <= config.features);
+    Self { hashes, features: config.features as u8 }
+  }
+
+  pub fn hashes(&self) -> &[T] {
+    &self.hashes
+  }
+
+  // Whether the shingleprint holds the hash of every shingle of its input,
+  // because the input had fewer distinct shingles than the feature count.
+  pub fn is_complete(&self) -> bool {
+    self.hashes.len() < self.features as usize
+  }
+}
+
+impl<T: Copy + Ord> Shingleprint<T> {
+  // Reassembles a shingleprint from its hashes, as returned by hashes().
+  // Returns None if they can't have come from a shingleprint with the given
+  // config: if they aren't strictly ascending, or there are too many.
+  pub fn from_hashes(hashes: &[T], config: &ShingleprintConfig) -> Option<Self> {
+    if hashes.len() > config.features || !hashes.windows(2).all(|w| w[0] < w[1]) {
+      return None;
+    }
+    Some(Self::new(hashes.iter().copied().collect(), config))
+  }
+}
+
+impl<T: Ord> Shingleprint<T> {
+  // Estimates the Jaccard similarity (|A ∩ B| / |A ∪ B|) of the shingle sets
+  // that the two shingleprints were computed from, using the bottom-k
+  // estimator: the k smallest hashes of A ∪ B are exactly the k smallest
+  // elements of the union of the two shingleprints, and the fraction of
+  // those which appear in both shingleprints is an unbiased estimate of the
+  // similarity.
+  // If both inputs were too short to contain any shingles, they are
+  // considered identical.
+  pub fn estimate_jaccard(&self, other: &Shingleprint<T>) -> f64 {
+    debug_assert_eq!(self.features, other.features);
+    let mut a = self.hashes.iter().peekable();
+    let mut b = other.hashes.iter().peekable();
+    let mut union_len = 0usize;
+    let mut intersection_len = 0usize;
+    while union_len < self.features as usize {
+      match (a.peek(), b.peek()) {
+        (Some(x), Some(y)) => match x.cmp(y) {
+          Ordering::Less => {
+            a.next();
+          }
+          Ordering::Greater => {
+            b.next();
+          }
+          Ordering::Equal => {
+            a.next();
+            b.next();
+            intersection_len += 1;
+          }
+        },
+        (Some(_), None) => {
+          a.next();
+        }
+        (None, Some(_)) => {
+          b.next();
+        }
+        (None, None) => break,
+      }
+      union_len += 1;
+    }
+    if union_len == 0 {
+      1.0
+    } else {
+      intersection_len as f64 / union_len as f64
+    }
+  }
+
+  // Estimated Jaccard distance (1 - similarity), in the range [0, 1].
+  pub fn distance(&self, other: &Shingleprint<T>) -> f64 {
+    1.0 - self.estimate_jaccard(other)
+  }
+
+  // The shingleprint of the union of the two shingle sets, such as that of a
+  // group of frames. This is exact rather than an estimate, by the same
+  // argument as for estimate_jaccard. Shingles that would straddle the join
+  // were the two inputs concatenated are not included.
+  pub fn union(&self, other: &Shingleprint<T>) -> Shingleprint<T>
+  where
+    T: Clone,
+  {
+    debug_assert_eq!(self.features, other.features);
+    let mut a = self.hashes.iter().peekable();
+    let mut b = other.hashes.iter().peekable();
+    let mut hashes = ArrayVec::new();
+    while hashes.len() < self.features as usize {
+      let next = match (a.peek(), b.peek()) {
+        (Some(x), Some(y)) => match x.cmp(y) {
+          Ordering::Less => a.next(),
+          Ordering::Greater => b.next(),
+          Ordering::Equal => {
+            b.next();
+            a.next()
+          }
+        },
+        (Some(_), None) => a.next(),
+        (None, _) => b.next(),
+      };
+      match next {
+        Some(hash) => hashes.push(hash.clone()),
+        None => break,
+      }
+    }
+    Shingleprint { hashes, features: self.features }
+  }
+}
+
+// Something done with the hashes of an input's shingles. This is a trait
+// rather than a closure so that it can be generic over the hashes' iterator
+// type and be inlined into each branch of shingle_hashes_specialised.
+pub(crate) trait ShingleHashesConsumer<T> {
+  type Output;
+  fn consume(self, hashes: impl Iterator<Item = T>) -> Self::Output;
+}
+
+// Hashes the input's shingles with H and passes them to the consumer. Passing
+// the default length as a constant lets the compiler specialise the hashing
+// of each shingle for it, which more than doubles the speed of the
+// hardware-accelerated hashers.
+#[inline(always)]
+pub(crate) fn shingle_hashes_specialised<H: hash::ShingleHasher, C: ShingleHashesConsumer<H::Hash>>(
+  input: &[u8],
+  shingle_len: usize,
+  consumer: C,
+) -> C::Output {
+  if shingle_len == SHINGLE_LEN {
+    consumer.consume(DefaultShingleLen(H::shingle_hashes(input, SHINGLE_LEN)))
+  } else {
+    consumer.consume(H::shingle_hashes(input, shingle_len))
+  }
+}
+
+// The hashes of shingles of the default length. Being a distinct type, it
+// gives consumers that can't be inlined, such as k_smallest_unique_avx2, a
+// monomorphisation of their own that can be specialised for it.
+struct DefaultShingleLen<I>(I);
+
+impl<I: Iterator> Iterator for DefaultShingleLen<I> {
+  type Item = I::Item;
+  #[inline(always)]
+  fn next(&mut self) -> Option<I::Item> {
+    self.0.next()
+  }
+  #[inline(always)]
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.0.size_hint()
+  }
+}
+
+// Takes the k smallest unique hashes.
+struct KSmallestUnique(usize);
+
+impl<T: Ord> ShingleHashesConsumer<T> for KSmallestUnique {
+  type Output = ArrayVec<T, MAX_SHINGLEPRINT_FEATURES>;
+  #[inline(always)]
+  fn consume(self, hashes: impl Iterator<Item = T>) -> Self::Output {
+    k_smallest_unique(hashes, self.0)
+  }
+}
+
+// Shingleprints with the given hasher, for evaluating alternatives to the
+// default. Shingleprints can only be compared with those from the same hasher.
+#[inline(always)]
+pub fn shingleprint_with<H: hash::ShingleHasher>(input: &[u8], config: &ShingleprintConfig) -> Shingleprint<H::Hash> {
+  let hashes = shingle_hashes_specialised::<H, _>(input, config.shingle_len, KSmallestUnique(config.features));
+  Shingleprint::new(hashes, config)
+}
+
+pub fn shingleprint_portable(input: &[u8], config: &ShingleprintConfig) -> Shingleprint {
+  shingleprint_with::<hash::Crc32cPortable>(input, config)
+}
 
-pub fn shingleprint_portable(input: &[u8]) -> Shingleprint {
-  let shingles = input.windows(SHINGLE_LEN);
-  let hashes = shingles.map(hash::hash_portable);
-  Shingleprint(k_smallest_unique::<_, SHINGLEPRINT_FEATURES>(hashes))
+// Same output as shingleprint_portable, in time independent of the shingle length.
+pub fn shingleprint_rolling(input: &[u8], config: &ShingleprintConfig) -> Shingleprint {
+  shingleprint_with::<hash::RollingCrc32c>(input, config)
 }
 
 // Undefined behaviour if the processor doesn't support the sse4.2 feature.
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "sse4.2")]
-pub unsafe fn shingleprint_sse(input: &[u8]) -> Shingleprint {
-  let shingles = input.windows(SHINGLE_LEN);
-  let hashes = shingles.map(|s| unsafe { hash::hash_sse(s) });
-  Shingleprint(k_smallest_unique::<_, SHINGLEPRINT_FEATURES>(hashes))
+pub unsafe fn shingleprint_sse(input: &[u8], config: &ShingleprintConfig) -> Shingleprint {
+  shingleprint_with::<hash::Crc32cSse>(input, config)
+}
+
+// Undefined behaviour if the processor doesn't support the sse4.2 and avx2 features.
+// Panics unless the config has the default feature count, which the AVX2
+// working set is sized for.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2,avx2")]
+pub unsafe fn shingleprint_avx2(input: &[u8], config: &ShingleprintConfig) -> Shingleprint {
+  const _: () = assert!(SHINGLEPRINT_FEATURES == 32);
+  assert_eq!(config.features, SHINGLEPRINT_FEATURES);
+  use hash::ShingleHash;
+  // Undefined behaviour if the processor doesn't support the avx2 feature.
+  struct KSmallestUniqueAvx2;
+  impl ShingleHashesConsumer<ShingleHash> for KSmallestUniqueAvx2 {
+    type Output = ArrayVec<ShingleHash, 32>;
+    #[inline(always)]
+    fn consume(self, hashes: impl Iterator<Item = ShingleHash>) -> Self::Output {
+      unsafe { k_smallest_unique_avx2(hashes) }
+    }
+  }
+  let hashes = shingle_hashes_specialised::<hash::Crc32cSse, _>(input, config.shingle_len, KSmallestUniqueAvx2);
+  Shingleprint::new(hashes.into_iter().collect(), config)
 }
 
-pub fn shingleprint(input: &[u8]) -> Shingleprint {
-  if is_x86_feature_detected!("sse4.2") {
-    unsafe { shingleprint_sse(input) }
+// Undefined behaviour if the processor doesn't support the crc feature.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+pub unsafe fn shingleprint_crc(input: &[u8], config: &ShingleprintConfig) -> Shingleprint {
+  shingleprint_with::<hash::Crc32cArm>(input, config)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn shingleprint(input: &[u8], config: &ShingleprintConfig) -> Shingleprint {
+  let sse = is_x86_feature_detected!("sse4.2");
+  if sse && is_x86_feature_detected!("avx2") && config.features == SHINGLEPRINT_FEATURES {
+    unsafe { shingleprint_avx2(input, config) }
+  } else if sse {
+    unsafe { shingleprint_sse(input, config) }
   } else {
-    shingleprint_portable(input)
+    shingleprint_rolling(input, config)
   }
 }
 
+#[cfg(target_arch = "aarch64")]
+pub fn shingleprint(input: &[u8], config: &ShingleprintConfig) -> Shingleprint {
+  if std::arch::is_aarch64_feature_detected!("crc") {
+    unsafe { shingleprint_crc(input, config) }
+  } else {
+    shingleprint_rolling(input, config)
+  }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn shingleprint(input: &[u8], config: &ShingleprintConfig) -> Shingleprint {
+  shingleprint_rolling(input, config)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::util::testing::random_bytes;
+  use std::collections::HashSet;
+
+  const CONFIG: &ShingleprintConfig = &ShingleprintConfig::DEFAULT;
 
-  const INPUT1: &'static [u8] =
+  const INPUT1: &[u8] =
     b"The quick brown fox jumps over the lazy dog, and jumps over the lazy dog once more.";
   const EXPECTED_OUTPUT1: [u32; 32] = [
     0x033587c5, // "umps over the la"
@@ -72,21 +378,235 @@ mod tests {
     0x74c764b5, // " the lazy dog on"
   ];
 
+  fn expected1() -> Shingleprint {
+    Shingleprint::new(EXPECTED_OUTPUT1.into_iter().collect(), CONFIG)
+  }
+
   #[test]
   fn test_portable() {
     assert_eq!(
-      shingleprint_portable(INPUT1),
-      Shingleprint(EXPECTED_OUTPUT1.into()),
+      shingleprint_portable(INPUT1, CONFIG),
+      expected1(),
+    );
+  }
+
+  #[test]
+  fn test_rolling() {
+    assert_eq!(
+      shingleprint_rolling(INPUT1, CONFIG),
+      expected1(),
     );
+    for seed in 0..20 {
+      let input = random_bytes(2 * seed + 1, 1000 * seed as usize);
+      assert_eq!(shingleprint_rolling(&input, CONFIG), shingleprint_portable(&input, CONFIG));
+    }
   }
 
+  #[cfg(target_arch = "x86_64")]
   #[test]
   fn test_sse() {
     if is_x86_feature_detected!("sse4.2") {
       assert_eq!(
-        unsafe { shingleprint_sse(INPUT1) },
-        Shingleprint(EXPECTED_OUTPUT1.into()),
+        unsafe { shingleprint_sse(INPUT1, CONFIG) },
+        expected1(),
+      );
+    }
+  }
+
+  #[cfg(target_arch = "x86_64")]
+  #[test]
+  fn test_avx2() {
+    if is_x86_feature_detected!("sse4.2") && is_x86_feature_detected!("avx2") {
+      assert_eq!(
+        unsafe { shingleprint_avx2(INPUT1, CONFIG) },
+        expected1(),
       );
+      for seed in 0..20 {
+        let input = random_bytes(2 * seed + 1, 1000 * seed as usize);
+        assert_eq!(unsafe { shingleprint_avx2(&input, CONFIG) }, shingleprint_portable(&input, CONFIG));
+      }
+    }
+  }
+
+  #[cfg(target_arch = "aarch64")]
+  #[test]
+  fn test_crc() {
+    if std::arch::is_aarch64_feature_detected!("crc") {
+      assert_eq!(
+        unsafe { shingleprint_crc(INPUT1, CONFIG) },
+        expected1(),
+      );
+    }
+  }
+
+  #[test]
+  fn test_with_xxh3() {
+    let a = random_bytes(1, 4096);
+    let b = mutate(&a, 2, 0.005);
+    let (sp_a, sp_b) = (shingleprint_with::<hash::Xxh3>(&a, CONFIG), shingleprint_with::<hash::Xxh3>(&b, CONFIG));
+    assert_eq!(sp_a.hashes.len(), SHINGLEPRINT_FEATURES);
+    assert!(sp_a.hashes.windows(2).all(|w| w[0] < w[1]));
+    let exact = exact_jaccard(&a, &b);
+    assert!((sp_a.estimate_jaccard(&sp_b) - exact).abs() < 0.3);
+    assert_eq!(sp_a.estimate_jaccard(&sp_a), 1.0);
+  }
+
+  // Overwrites roughly `fraction` of the bytes of `input` with random values.
+  fn mutate(input: &[u8], seed: u64, fraction: f64) -> Vec<u8> {
+    let noise = random_bytes(seed, input.len() * 2);
+    let threshold = (fraction * 256.0) as u8;
+    input
+      .iter()
+      .zip(noise.chunks_exact(2))
+      .map(|(&byte, noise)| if noise[0] < threshold { noise[1] } else { byte })
+      .collect()
+  }
+
+  fn exact_jaccard(a: &[u8], b: &[u8]) -> f64 {
+    exact_jaccard_with(a, b, SHINGLE_LEN)
+  }
+
+  fn exact_jaccard_with(a: &[u8], b: &[u8], shingle_len: usize) -> f64 {
+    let a: HashSet<&[u8]> = a.windows(shingle_len).collect();
+    let b: HashSet<&[u8]> = b.windows(shingle_len).collect();
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+  }
+
+  #[test]
+  fn test_estimate_jaccard_identical() {
+    let sp = shingleprint_portable(INPUT1, CONFIG);
+    assert_eq!(sp.estimate_jaccard(&sp), 1.0);
+    assert_eq!(sp.distance(&sp), 0.0);
+  }
+
+  #[test]
+  fn test_estimate_jaccard_disjoint() {
+    let a = shingleprint_portable(&[b'a'; 100], CONFIG);
+    let b = shingleprint_portable(&[b'b'; 100], CONFIG);
+    assert_eq!(a.estimate_jaccard(&b), 0.0);
+    assert_eq!(a.distance(&b), 1.0);
+  }
+
+  #[test]
+  fn test_estimate_jaccard_empty() {
+    let empty = shingleprint_portable(b"", CONFIG);
+    let nonempty = shingleprint_portable(INPUT1, CONFIG);
+    assert_eq!(empty.estimate_jaccard(&empty), 1.0);
+    assert_eq!(empty.estimate_jaccard(&nonempty), 0.0);
+    assert_eq!(nonempty.estimate_jaccard(&empty), 0.0);
+  }
+
+  #[test]
+  fn test_estimate_jaccard_exact_for_small_inputs() {
+    // With fewer than SHINGLEPRINT_FEATURES distinct shingles in the union,
+    // the estimator sees the whole of both sets and so is exact.
+    let a = &INPUT1[..SHINGLE_LEN + 12];
+    let b = &INPUT1[8..SHINGLE_LEN + 16];
+    let got = shingleprint_portable(a, CONFIG).estimate_jaccard(&shingleprint_portable(b, CONFIG));
+    assert_eq!(got, exact_jaccard(a, b));
+  }
+
+  #[test]
+  fn test_estimate_jaccard_symmetric() {
+    let a = random_bytes(1, 4096);
+    let b = mutate(&a, 2, 0.01);
+    let (a, b) = (shingleprint_portable(&a, CONFIG), shingleprint_portable(&b, CONFIG));
+    assert_eq!(a.estimate_jaccard(&b), b.estimate_jaccard(&a));
+  }
+
+  #[test]
+  fn test_estimate_jaccard_against_exact() {
+    const TRIALS: u64 = 30;
+    for fraction in [0.001, 0.005, 0.01, 0.02, 0.05] {
+      let mut total_error = 0.0;
+      for trial in 0..TRIALS {
+        let a = random_bytes(trial * 2 + 1, 4096);
+        let b = mutate(&a, trial * 2 + 2, fraction);
+        let exact = exact_jaccard(&a, &b);
+        let estimate = shingleprint_portable(&a, CONFIG).estimate_jaccard(&shingleprint_portable(&b, CONFIG));
+        // Standard error of the estimator is sqrt(J(1-J)/k) <= 0.09 for k = 32.
+        assert!(
+          (estimate - exact).abs() < 0.3,
+          "fraction {fraction}, trial {trial}: estimate {estimate}, exact {exact}",
+        );
+        total_error += estimate - exact;
+      }
+      let mean_error = total_error / TRIALS as f64;
+      assert!(
+        mean_error.abs() < 0.05,
+        "fraction {fraction}: mean error {mean_error}",
+      );
+    }
+  }
+
+  #[test]
+  fn test_from_hashes() {
+    let sp = shingleprint_portable(INPUT1, CONFIG);
+    assert_eq!(Shingleprint::from_hashes(sp.hashes(), CONFIG), Some(sp.clone()));
+    assert_eq!(Shingleprint::from_hashes(&[], CONFIG), Some(shingleprint_portable(b"", CONFIG)));
+    assert_eq!(Shingleprint::from_hashes(&[2, 1], CONFIG), None);
+    assert_eq!(Shingleprint::from_hashes(&[1, 1], CONFIG), None);
+    let small = ShingleprintConfig::new(SHINGLE_LEN, MIN_SHINGLEPRINT_FEATURES).unwrap();
+    assert_eq!(Shingleprint::from_hashes(sp.hashes(), &small), None);
+  }
+
+  #[test]
+  fn test_config() {
+    assert_eq!(ShingleprintConfig::new(SHINGLE_LEN, SHINGLEPRINT_FEATURES), Ok(ShingleprintConfig::DEFAULT));
+    assert_eq!(ShingleprintConfig::new(0, 32), Err(ConfigError::ShingleLen(0)));
+    assert_eq!(ShingleprintConfig::new(MAX_SHINGLE_LEN + 1, 32), Err(ConfigError::ShingleLen(MAX_SHINGLE_LEN + 1)));
+    assert_eq!(ShingleprintConfig::new(16, MIN_SHINGLEPRINT_FEATURES - 1), Err(ConfigError::Features(15)));
+    assert_eq!(ShingleprintConfig::new(16, MAX_SHINGLEPRINT_FEATURES + 1), Err(ConfigError::Features(65)));
+  }
+
+  #[test]
+  fn test_custom_config() {
+    for (shingle_len, features) in [(1, 16), (8, 48), (MAX_SHINGLE_LEN, MAX_SHINGLEPRINT_FEATURES), (24, 32)] {
+      let config = ShingleprintConfig::new(shingle_len, features).unwrap();
+      for seed in 0..5 {
+        let input = random_bytes(2 * seed + 1, 2000);
+        let expected = shingleprint_portable(&input, &config);
+        assert_eq!(expected.hashes.len(), features);
+        assert!(expected.hashes.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(shingleprint_rolling(&input, &config), expected);
+        assert_eq!(shingleprint(&input, &config), expected);
+      }
+      // Inputs shorter than a shingle have none.
+      let sp = shingleprint(&INPUT1[..shingle_len - 1], &config);
+      assert!(sp.hashes.is_empty() && sp.is_complete());
+    }
+  }
+
+  #[test]
+  fn test_estimate_jaccard_custom_config() {
+    let config = ShingleprintConfig::new(8, MAX_SHINGLEPRINT_FEATURES).unwrap();
+    let a = random_bytes(1, 4096);
+    let b = mutate(&a, 2, 0.005);
+    let estimate = shingleprint(&a, &config).estimate_jaccard(&shingleprint(&b, &config));
+    assert!((estimate - exact_jaccard_with(&a, &b, 8)).abs() < 0.25);
+    // Short inputs are still estimated exactly.
+    let (a, b) = (&INPUT1[..20], &INPUT1[4..24]);
+    let estimate = shingleprint(a, &config).estimate_jaccard(&shingleprint(b, &config));
+    assert_eq!(estimate, exact_jaccard_with(a, b, 8));
+  }
+
+  #[test]
+  fn test_union() {
+    use hash::ShingleHasher;
+    for config in [ShingleprintConfig::DEFAULT, ShingleprintConfig::new(8, MAX_SHINGLEPRINT_FEATURES).unwrap()] {
+      let len = config.shingle_len;
+      let a = random_bytes(1, 3000);
+      let b = mutate(&a, 2, 0.01);
+      let cases: [(&[u8], &[u8]); 5] = [(&a, &b), (&a, &a), (&a, b""), (&INPUT1[..30], &INPUT1[20..50]), (b"", b"")];
+      for (a, b) in cases {
+        let (sp_a, sp_b) = (shingleprint_portable(a, &config), shingleprint_portable(b, &config));
+        let hashes = hash::Crc32cPortable::shingle_hashes(a, len).chain(hash::Crc32cPortable::shingle_hashes(b, len));
+        let expected = Shingleprint::new(k_smallest_unique(hashes, config.features), &config);
+        assert_eq!(sp_a.union(&sp_b), expected);
+        assert_eq!(sp_b.union(&sp_a), expected);
+      }
     }
+    let short = shingleprint_portable(&INPUT1[..20], CONFIG);
+    assert!(short.union(&short).is_complete());
   }
 }
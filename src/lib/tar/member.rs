@@ -0,0 +1,319 @@
+// Interpretation of a frame as a whole: a member's header, together with the
+// extension records before it (GNU long names and links, and pax extended
+// headers) and any sparse map, which between them determine the member's
+// real name and how its content is laid out.
+
+use crate::tar::pax::{self, parse_decimal};
+use crate::tar::sparse::{self, SparseExtension, SparseMap};
+use crate::tar::{Format, Header};
+
+// The offset is that of the byte within the frame at which the problem was
+// detected.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseMemberError(pub usize, pub &'static str);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Member<'a> {
+  // The member's own header, after any extension records.
+  pub header: Header<'a>,
+  pub header_offset: usize,
+  // Taken from a pax record if there is one, else a GNU long name or link
+  // record, else the header itself.
+  pub name: Vec<u8>,
+  pub linkname: Vec<u8>,
+  pub sparse: Option<SparseMap>,
+  // Where the member's stored data starts within the frame: after any sparse
+  // extension blocks, or pax 1.0 sparse map.
+  pub data_offset: usize,
+}
+
+impl<'a> Member<'a> {
+  pub fn parse(frame: &'a [u8]) -> Result<Member<'a>, ParseMemberError> {
+    let mut offset = 0;
+    let mut long_name = None;
+    let mut long_linkname = None;
+    let mut pax = PaxFields::default();
+    let header = loop {
+      let block = frame.get(offset..offset + 512).ok_or(ParseMemberError(offset, "truncated header"))?;
+      let header = Header(block.try_into().unwrap());
+      if !header.is_prefix() {
+        break header;
+      }
+      let content_offset = offset + 512;
+      let content = header
+        .content_len()
+        .ok()
+        .and_then(|len| frame.get(content_offset..content_offset.checked_add(usize::try_from(len).ok()?)?))
+        .ok_or(ParseMemberError(offset + 124, "bad extension record size"))?;
+      match header.type_flag() {
+        b'L' => long_name = Some(until_nul(content)),
+        b'K' => long_linkname = Some(until_nul(content)),
+        _ => pax.update(content).map_err(|pax::ParsePaxError(at, msg)| ParseMemberError(content_offset + at, msg))?,
+      }
+      offset = content_offset + content.len().next_multiple_of(512);
+    };
+    let header_offset = offset;
+    let mut data_offset = header_offset + 512;
+    let mut extended = header.is_extended();
+    while extended {
+      let block = frame.get(data_offset..data_offset + 512).ok_or(ParseMemberError(data_offset, "truncated sparse map"))?;
+      extended = SparseExtension(block.try_into().unwrap()).is_extended();
+      data_offset += 512;
+    }
+    let sparse = if header.type_flag() == b'S' {
+      let extensions = &frame[header_offset + 512..data_offset];
+      let map = sparse::parse_gnu(header, extensions)
+        .map_err(|sparse::ParseSparseError(at, msg)| ParseMemberError(header_offset + at, msg))?;
+      Some(map)
+    } else if let Some(real_size) = pax.sparse_1_real_size {
+      let (map, len) = sparse::parse_pax_1(&frame[data_offset..], real_size)
+        .map_err(|sparse::ParseSparseError(at, msg)| ParseMemberError(data_offset + at, msg))?;
+      data_offset += len;
+      Some(map)
+    } else {
+      pax.sparse_0
+    };
+    let name = real_name(pax.sparse_name.or(pax.path), long_name, header);
+    let linkname = pax.linkpath.or(long_linkname).unwrap_or(header.linkname()).to_vec();
+    Ok(Member { header, header_offset, name, linkname, sparse, data_offset })
+  }
+}
+
+// The name of the member a frame is for, as far as it can be told from the
+// frame or the start of one. Unlike Member::parse, this tolerates malformed
+// extension records, taking the name from those before the fault, or else
+// from the member's header if it is reached.
+pub fn member_name(frame: &[u8]) -> Option<Vec<u8>> {
+  let mut offset = 0;
+  let mut long_name = None;
+  let mut pax_name = None;
+  while let Some(block) = frame.get(offset..offset + 512) {
+    let header = Header(block.try_into().unwrap());
+    if !header.is_prefix() {
+      return Some(real_name(pax_name, long_name, header));
+    }
+    let content_offset = offset + 512;
+    let Some(content) = header
+      .content_len()
+      .ok()
+      .and_then(|len| frame.get(content_offset..content_offset.checked_add(usize::try_from(len).ok()?)?))
+    else {
+      break;
+    };
+    match header.type_flag() {
+      b'L' => long_name = Some(until_nul(content)),
+      b'K' => {}
+      _ => {
+        let mut path = None;
+        let mut sparse_name = None;
+        for (key, value) in pax::records(content).map_while(Result::ok) {
+          let value = Some(value).filter(|value| !value.is_empty());
+          match key {
+            b"path" => path = value,
+            b"GNU.sparse.name" => sparse_name = value,
+            _ => {}
+          }
+        }
+        pax_name = sparse_name.or(path).or(pax_name);
+      }
+    }
+    offset = content_offset + content.len().next_multiple_of(512);
+  }
+  pax_name.or(long_name).map(<[u8]>::to_vec)
+}
+
+// Names from pax records take precedence over GNU long names, which take
+// precedence over the header's own.
+fn real_name(pax_name: Option<&[u8]>, long_name: Option<&[u8]>, header: Header) -> Vec<u8> {
+  match pax_name.or(long_name) {
+    Some(name) => name.to_vec(),
+    None => match header.format() {
+      Format::Ustar if !header.prefix().is_empty() => [header.prefix(), b"/", header.name()].concat(),
+      _ => header.name().to_vec(),
+    },
+  }
+}
+
+fn until_nul(content: &[u8]) -> &[u8] {
+  content.split(|&byte| byte == 0).next().unwrap_or_default()
+}
+
+// What the x records in a frame say about its member. Only what bears on the
+// member's name and layout is parsed, so that bad values of other keys are no
+// obstacle.
+#[derive(Debug, Default)]
+struct PaxFields<'a> {
+  path: Option<&'a [u8]>,
+  linkpath: Option<&'a [u8]>,
+  sparse_name: Option<&'a [u8]>,
+  sparse_0: Option<SparseMap>,
+  // Set for a pax 1.0 sparse file, whose map is at the start of its content.
+  sparse_1_real_size: Option<u64>,
+}
+
+impl<'a> PaxFields<'a> {
+  fn update(&mut self, content: &'a [u8]) -> Result<(), pax::ParsePaxError> {
+    let mut major = None;
+    let mut real_size = None;
+    let mut records = pax::records(content);
+    loop {
+      let offset = records.offset();
+      let Some(record) = records.next() else { break };
+      let (key, value) = record?;
+      let value = Some(value).filter(|value| !value.is_empty());
+      match key {
+        b"path" => self.path = value,
+        b"linkpath" => self.linkpath = value,
+        b"GNU.sparse.name" => self.sparse_name = value,
+        b"GNU.sparse.major" => major = value,
+        b"GNU.sparse.realsize" => {
+          real_size = Some(value.and_then(parse_decimal).ok_or(pax::ParsePaxError(offset, "bad sparse real size"))?);
+        }
+        _ => {}
+      }
+    }
+    match (major, real_size) {
+      (Some(b"1"), Some(real_size)) => self.sparse_1_real_size = Some(real_size),
+      (Some(b"1"), None) => return Err(pax::ParsePaxError(0, "sparse file without real size")),
+      (Some(_), _) => return Err(pax::ParsePaxError(0, "unsupported sparse format")),
+      (None, _) => {
+        let map = sparse::parse_pax_0(pax::records(content))
+          .map_err(|sparse::ParseSparseError(at, msg)| pax::ParsePaxError(at, msg))?;
+        if map.is_some() {
+          self.sparse_0 = map;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tar::pax::build_records;
+  use crate::tar::sparse::SparseEntry;
+  use crate::util::testing::{gnu_sparse_member, header, member};
+
+  #[test]
+  fn test_plain() {
+    let frame = member(b"a.txt", b'0', b"hello");
+    let member = Member::parse(&frame).unwrap();
+    assert_eq!(member.name, b"a.txt");
+    assert_eq!(member.linkname, b"");
+    assert_eq!((member.header_offset, member.data_offset), (0, 512));
+    assert_eq!(member.sparse, None);
+  }
+
+  #[test]
+  fn test_ustar_prefix() {
+    let mut block = header(b"name", b'0', 0);
+    block[345..353].copy_from_slice(b"some/dir");
+    let checksum = Header(&block).unsigned_checksum();
+    block[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    assert_eq!(Member::parse(&block).unwrap().name, b"some/dir/name");
+  }
+
+  #[test]
+  fn test_gnu_long_names() {
+    let frame = [
+      member(b"././@LongLink", b'L', b"some/very/long/name\0"),
+      member(b"././@LongLink", b'K', b"some/very/long/target\0"),
+      member(b"some/very/long/nam", b'2', b""),
+    ]
+    .concat();
+    let member = Member::parse(&frame).unwrap();
+    assert_eq!(member.name, b"some/very/long/name");
+    assert_eq!(member.linkname, b"some/very/long/target");
+    assert_eq!(member.header_offset, 2048);
+    assert_eq!(member.header.type_flag(), b'2');
+  }
+
+  #[test]
+  fn test_pax_names() {
+    let records = build_records(&[(b"path", b"pax/name"), (b"linkpath", b"pax/target"), (b"mtime", b"bad")]);
+    let frame = [
+      member(b"././@LongLink", b'L', b"long/name\0"),
+      member(b"PaxHeaders/a", b'x', &records),
+      member(b"a", b'1', b""),
+    ]
+    .concat();
+    let member = Member::parse(&frame).unwrap();
+    assert_eq!(member.name, b"pax/name");
+    assert_eq!(member.linkname, b"pax/target");
+  }
+
+  #[test]
+  fn test_gnu_sparse() {
+    // Four entries in the header, and one in an extension block.
+    let pairs = [(0, 128), (4096, 128), (8192, 128), (12288, 128), (16384, 512)];
+    let frame = gnu_sparse_member(b"sparse", &pairs, 32768);
+    let member = Member::parse(&frame).unwrap();
+    assert_eq!(member.name, b"sparse");
+    assert_eq!(member.data_offset, 1024);
+    let entries = pairs.iter().map(|&(offset, len)| SparseEntry { offset, len }).collect();
+    assert_eq!(member.sparse, Some(SparseMap { real_size: 32768, entries }));
+    assert_eq!(Member::parse(&frame[..1000]), Err(ParseMemberError(512, "truncated sparse map")));
+  }
+
+  #[test]
+  fn test_pax_sparse_0() {
+    let records = build_records(&[
+      (b"GNU.sparse.size", b"10000"),
+      (b"GNU.sparse.name", b"sparse.bin"),
+      (b"GNU.sparse.map", b"0,512,9488,512"),
+    ]);
+    let frame = [member(b"PaxHeaders/s", b'x', &records), member(b"GNUSparseFile.1/sparse.bin", b'0', &[b'x'; 1024])]
+      .concat();
+    let member = Member::parse(&frame).unwrap();
+    assert_eq!(member.name, b"sparse.bin");
+    assert_eq!(member.data_offset, 1536);
+    assert_eq!(member.sparse.unwrap().entries, [SparseEntry { offset: 0, len: 512 }, SparseEntry { offset: 9488, len: 512 }]);
+  }
+
+  #[test]
+  fn test_pax_sparse_1() {
+    let records = build_records(&[
+      (b"GNU.sparse.major", b"1"),
+      (b"GNU.sparse.minor", b"0"),
+      (b"GNU.sparse.name", b"sparse.bin"),
+      (b"GNU.sparse.realsize", b"10000"),
+    ]);
+    let mut content = b"1\n9488\n512\n".to_vec();
+    content.resize(1024, 0);
+    let frame = [member(b"PaxHeaders/s", b'x', &records), member(b"GNUSparseFile.1/sparse.bin", b'0', &content)].concat();
+    let member = Member::parse(&frame).unwrap();
+    assert_eq!(member.name, b"sparse.bin");
+    assert_eq!(member.data_offset, 2048);
+    let expected = SparseMap { real_size: 10000, entries: vec![SparseEntry { offset: 9488, len: 512 }] };
+    assert_eq!(member.sparse, Some(expected));
+  }
+
+  #[test]
+  fn test_member_name() {
+    let records = build_records(&[(b"path", b"pax/name")]);
+    let frame = [
+      member(b"././@LongLink", b'L', b"long/name\0"),
+      member(b"PaxHeaders/a", b'x', &[&records[..], b"9 size=x\n"].concat()),
+      member(b"a", b'0', b""),
+    ]
+    .concat();
+    assert_eq!(member_name(&frame).as_deref(), Some(&b"pax/name"[..]));
+    // Only the records before the end of a truncated frame count.
+    assert_eq!(member_name(&frame[..1100]).as_deref(), Some(&b"long/name"[..]));
+    assert_eq!(member_name(&frame[..400]), None);
+    let frame = [member(b"PaxHeaders/a", b'x', b"9 path=x"), member(b"a", b'0', b"")].concat();
+    assert_eq!(member_name(&frame).as_deref(), Some(&b"a"[..]));
+    assert_eq!(member_name(b""), None);
+  }
+
+  #[test]
+  fn test_malformed() {
+    assert_eq!(Member::parse(b""), Err(ParseMemberError(0, "truncated header")));
+    let frame = member(b"././@LongLink", b'L', b"name");
+    assert_eq!(Member::parse(&frame), Err(ParseMemberError(1024, "truncated header")));
+    let frame = [member(b"PaxHeaders/a", b'x', b"9 path=x"), member(b"a", b'0', b"")].concat();
+    assert_eq!(Member::parse(&frame[..515]), Err(ParseMemberError(124, "bad extension record size")));
+    assert_eq!(Member::parse(&frame), Err(ParseMemberError(520, "record extends past end of header")));
+  }
+}
@@ -0,0 +1,403 @@
+pub mod member;
+pub mod pax;
+pub mod sparse;
+
+pub use member::{member_name, Member};
+use std::fmt;
+
+// The offset is that of the byte within the field at which the problem was
+// detected.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseNumericError(pub usize, pub &'static str);
+
+impl fmt::Display for ParseNumericError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} at byte {} of field", self.1, self.0)
+  }
+}
+
+impl std::error::Error for ParseNumericError {}
+
+pub fn parse_numeric<const LEN: usize>(mut input: [u8; LEN]) -> Result<u64, ParseNumericError> {
+  if input[0] & 0x80 != 0 {
+    // Packed binary format.
+    // Apart from the MSB of input[0], all bits before input[LEN-8] must be zeroes,
+    // otherwise the logical value is too large to hold in a u64.
+    input[0] &= 0x7F;
+    if let Some(i) = input[..LEN - 8].iter().position(|&byte| byte != 0) {
+      return Err(ParseNumericError(i, "value too large"));
+    }
+    let input: &[u8] = &input[LEN - 8..];
+    let input: &[u8; 8] = input.try_into().unwrap();
+    Ok(u64::from_be_bytes(*input))
+  } else {
+    // ASCII octal format.
+    let mut accum = 0;
+    for (i, byte) in input.into_iter().enumerate() {
+      match byte {
+        b'0'..=b'7' => {
+          accum = accum * 8 + u64::from(byte - b'0');
+        }
+        b'\x00' | b' ' => {}
+        _ => return Err(ParseNumericError(i, "invalid octal digit")),
+      }
+    }
+    Ok(accum)
+  }
+}
+
+// Inverse of parse_numeric. Uses the ASCII octal format where the value fits,
+// and the packed binary format otherwise.
+pub fn format_numeric<const LEN: usize>(value: u64) -> [u8; LEN] {
+  let mut output = [0u8; LEN];
+  if value < 1 << (3 * (LEN - 1)) {
+    // LEN-1 octal digits followed by a NUL.
+    let mut remaining = value;
+    for byte in output[..LEN - 1].iter_mut().rev() {
+      *byte = b'0' + (remaining % 8) as u8;
+      remaining /= 8;
+    }
+  } else {
+    output[LEN - 8..].copy_from_slice(&value.to_be_bytes());
+    output[0] |= 0x80;
+  }
+  output
+}
+
+// Returns the portion of a string field before the first NUL byte (or the
+// whole field, if it is not NUL-terminated).
+fn parse_text(input: &[u8]) -> &[u8] {
+  match input.iter().position(|&byte| byte == 0) {
+    Some(len) => &input[..len],
+    None => input,
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Format {
+  // Original Unix V7 format; no magic.
+  V7,
+  // POSIX.1-1988 ustar format.
+  Ustar,
+  // GNU tar's pre-POSIX ustar variant.
+  Gnu,
+  // POSIX.1-2001 pax format, i.e. ustar with extended headers. Only the
+  // extended headers themselves can be identified as such; the ordinary
+  // members of a pax archive look like plain ustar.
+  Pax,
+}
+
+// Two zero-filled blocks mark the end of an archive.
+pub const END_OF_ARCHIVE: [u8; 1024] = [0; 1024];
+
+// Builds a ustar header block with the given name, type and content length,
+// and otherwise neutral metadata (mode 0644, owned by root, mtime of zero).
+pub fn build_header(name: &[u8], type_flag: u8, content_len: u64) -> [u8; 512] {
+  assert!(name.len() <= 100, "name too long for a ustar header");
+  let mut block = [0u8; 512];
+  block[..name.len()].copy_from_slice(name);
+  block[100..108].copy_from_slice(&format_numeric::<8>(0o644));
+  block[108..116].copy_from_slice(&format_numeric::<8>(0));
+  block[116..124].copy_from_slice(&format_numeric::<8>(0));
+  block[124..136].copy_from_slice(&format_numeric::<12>(content_len));
+  block[136..148].copy_from_slice(&format_numeric::<12>(0));
+  block[156] = type_flag;
+  block[257..263].copy_from_slice(b"ustar\0");
+  block[263..265].copy_from_slice(b"00");
+  let checksum = Header(&block).unsigned_checksum();
+  // Six octal digits, a NUL and a space, as traditionally written.
+  block[148..155].copy_from_slice(&format_numeric::<7>(checksum));
+  block[155] = b' ';
+  block
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Header<'a>(pub &'a [u8; 512]);
+
+impl<'a> Header<'a> {
+  fn field<const LEN: usize>(self, offset: usize) -> &'a [u8; LEN] {
+    self.0[offset..offset + LEN].try_into().unwrap()
+  }
+
+  pub fn name(self) -> &'a [u8] {
+    parse_text(self.field::<100>(0))
+  }
+  pub fn mode(self) -> Result<u64, ParseNumericError> {
+    parse_numeric(*self.field::<8>(100))
+  }
+  pub fn uid(self) -> Result<u64, ParseNumericError> {
+    parse_numeric(*self.field::<8>(108))
+  }
+  pub fn gid(self) -> Result<u64, ParseNumericError> {
+    parse_numeric(*self.field::<8>(116))
+  }
+  pub fn content_len(self) -> Result<u64, ParseNumericError> {
+    parse_numeric(*self.field::<12>(124))
+  }
+  pub fn mtime(self) -> Result<u64, ParseNumericError> {
+    parse_numeric(*self.field::<12>(136))
+  }
+  pub fn checksum(self) -> Result<u64, ParseNumericError> {
+    parse_numeric(*self.field::<8>(148))
+  }
+  pub fn type_flag(self) -> u8 {
+    self.0[156]
+  }
+  pub fn linkname(self) -> &'a [u8] {
+    parse_text(self.field::<100>(157))
+  }
+  pub fn magic(self) -> &'a [u8; 6] {
+    self.field(257)
+  }
+  pub fn version(self) -> &'a [u8; 2] {
+    self.field(263)
+  }
+  pub fn uname(self) -> &'a [u8] {
+    parse_text(self.field::<32>(265))
+  }
+  pub fn gname(self) -> &'a [u8] {
+    parse_text(self.field::<32>(297))
+  }
+  pub fn devmajor(self) -> Result<u64, ParseNumericError> {
+    parse_numeric(*self.field::<8>(329))
+  }
+  pub fn devminor(self) -> Result<u64, ParseNumericError> {
+    parse_numeric(*self.field::<8>(337))
+  }
+  // Only meaningful for the ustar and pax formats; GNU tar stores other
+  // information in these bytes.
+  pub fn prefix(self) -> &'a [u8] {
+    parse_text(self.field::<155>(345))
+  }
+
+  pub fn format(self) -> Format {
+    match (self.magic(), self.version()) {
+      (b"ustar ", b" \0") => Format::Gnu,
+      (b"ustar\0", _) if matches!(self.type_flag(), b'x' | b'g') => Format::Pax,
+      (b"ustar\0", _) => Format::Ustar,
+      _ => Format::V7,
+    }
+  }
+
+  // The checksum is the sum of all bytes in the header, with the checksum
+  // field itself taken to be filled with spaces. POSIX specifies that the
+  // bytes are treated as unsigned, but some historic implementations summed
+  // them as signed chars.
+  pub fn unsigned_checksum(self) -> u64 {
+    let sum: u64 = self.0.iter().map(|&byte| u64::from(byte)).sum();
+    let field: u64 = self.field::<8>(148).iter().map(|&byte| u64::from(byte)).sum();
+    sum - field + 8 * u64::from(b' ')
+  }
+  pub fn signed_checksum(self) -> i64 {
+    let sum: i64 = self.0.iter().map(|&byte| i64::from(byte as i8)).sum();
+    let field: i64 = self.field::<8>(148).iter().map(|&byte| i64::from(byte as i8)).sum();
+    sum - field + 8 * i64::from(b' ')
+  }
+  pub fn verify_checksum(self) -> bool {
+    match self.checksum() {
+      Ok(expected) => {
+        expected == self.unsigned_checksum() || i64::try_from(expected) == Ok(self.signed_checksum())
+      }
+      Err(_) => false,
+    }
+  }
+
+  pub fn is_zero(self) -> bool {
+    self.0.iter().all(|&byte| byte == 0)
+  }
+  pub fn is_null(self) -> bool {
+    self.type_flag() == 0 && self.0[0] == 0
+  }
+  pub fn is_prefix(self) -> bool {
+    // x = metadata for the next file (PAX extension)
+    // K = long linkname for the next file (GNU extension)
+    // L = long name for the next file (GNU extension)
+    matches!(self.type_flag(), b'x' | b'K' | b'L')
+  }
+  // x or g: a pax extended header, whose content is a list of records (see
+  // the pax module).
+  pub fn is_pax_header(self) -> bool {
+    matches!(self.type_flag(), b'x' | b'g')
+  }
+  // g = metadata for all later files (PAX extension)
+  pub fn is_global(self) -> bool {
+    self.type_flag() == b'g'
+  }
+  // For a GNU sparse member (S), whether its sparse map continues in
+  // extension blocks between the header and the content.
+  pub fn is_extended(self) -> bool {
+    self.type_flag() == b'S' && self.0[482] != 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Builds a header block with the given fields, and a valid checksum.
+  fn make_header(name: &[u8], type_flag: u8, magic: &[u8; 6], version: &[u8; 2]) -> [u8; 512] {
+    let mut block = [0u8; 512];
+    block[..name.len()].copy_from_slice(name);
+    block[100..108].copy_from_slice(b"0000644\0");
+    block[108..116].copy_from_slice(b"0001750\0");
+    block[116..124].copy_from_slice(b"0000144\0");
+    block[124..136].copy_from_slice(b"00000000017\0");
+    block[136..148].copy_from_slice(b"14530254712\0");
+    block[156] = type_flag;
+    block[157..164].copy_from_slice(b"target\0");
+    block[257..263].copy_from_slice(magic);
+    block[263..265].copy_from_slice(version);
+    block[265..270].copy_from_slice(b"kier\0");
+    block[297..303].copy_from_slice(b"users\0");
+    block[329..337].copy_from_slice(b"0000010\0");
+    block[337..345].copy_from_slice(b"0000003\0");
+    block[345..354].copy_from_slice(b"some/dir\0");
+    let checksum = Header(&block).unsigned_checksum();
+    block[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    block
+  }
+
+  #[test]
+  fn test_parse_numeric_8_packed() {
+    assert_eq!(
+      parse_numeric([0x81, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]).ok(),
+      Some(0x0102030405060708),
+    );
+  }
+
+  #[test]
+  fn test_parse_numeric_12_packed() {
+    assert_eq!(
+      parse_numeric([0x80, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]).ok(),
+      Some(0x0102030405060708),
+    );
+  }
+
+  #[test]
+  fn test_parse_numeric_12_packed_overflow() {
+    assert_eq!(
+      parse_numeric([0x80, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09]),
+      Err(ParseNumericError(3, "value too large")),
+    );
+  }
+
+  #[test]
+  fn test_parse_numeric_12_ascii_small() {
+    assert_eq!(parse_numeric(*b"00000000017\x00").ok(), Some(15),)
+  }
+
+  #[test]
+  fn test_parse_numeric_12_ascii_large() {
+    assert_eq!(
+      parse_numeric(*b"76400000000\x00").ok(),
+      Some(8000 * 1024 * 1024),
+    )
+  }
+
+  #[test]
+  fn test_parse_numeric_12_ascii_pre_posix() {
+    assert_eq!(parse_numeric(*b"         17 ").ok(), Some(15),)
+  }
+
+  #[test]
+  fn test_parse_numeric_8_ascii_invalid() {
+    assert_eq!(parse_numeric(*b"0000x44\0"), Err(ParseNumericError(4, "invalid octal digit")))
+  }
+
+  #[test]
+  fn test_format_numeric_ascii() {
+    assert_eq!(&format_numeric::<12>(15), b"00000000017\x00");
+    assert_eq!(&format_numeric::<8>(0o7777777), b"7777777\x00");
+  }
+
+  #[test]
+  fn test_format_numeric_packed() {
+    assert_eq!(format_numeric::<8>(0o10000000), [0x80, 0, 0, 0, 0, 0x20, 0, 0]);
+    assert_eq!(parse_numeric(format_numeric::<12>(u64::MAX)).ok(), Some(u64::MAX));
+  }
+
+  #[test]
+  fn test_build_header() {
+    let block = build_header(b"some/file", b'0', 8000 * 1024 * 1024);
+    let header = Header(&block);
+    assert_eq!(header.name(), b"some/file");
+    assert_eq!(header.type_flag(), b'0');
+    assert_eq!(header.content_len().ok(), Some(8000 * 1024 * 1024));
+    assert_eq!(header.format(), Format::Ustar);
+    assert!(header.verify_checksum());
+  }
+
+  #[test]
+  fn test_fields() {
+    let block = make_header(b"hello.txt", b'0', b"ustar\0", b"00");
+    let header = Header(&block);
+    assert_eq!(header.name(), b"hello.txt");
+    assert_eq!(header.mode().ok(), Some(0o644));
+    assert_eq!(header.uid().ok(), Some(1000));
+    assert_eq!(header.gid().ok(), Some(100));
+    assert_eq!(header.content_len().ok(), Some(15));
+    assert_eq!(header.mtime().ok(), Some(1700878794));
+    assert_eq!(header.checksum().ok(), Some(header.unsigned_checksum()));
+    assert_eq!(header.type_flag(), b'0');
+    assert_eq!(header.linkname(), b"target");
+    assert_eq!(header.magic(), b"ustar\0");
+    assert_eq!(header.version(), b"00");
+    assert_eq!(header.uname(), b"kier");
+    assert_eq!(header.gname(), b"users");
+    assert_eq!(header.devmajor().ok(), Some(8));
+    assert_eq!(header.devminor().ok(), Some(3));
+    assert_eq!(header.prefix(), b"some/dir");
+  }
+
+  #[test]
+  fn test_unterminated_name() {
+    let mut block = make_header(b"", b'0', b"ustar\0", b"00");
+    block[..100].fill(b'a');
+    assert_eq!(Header(&block).name(), [b'a'; 100]);
+  }
+
+  #[test]
+  fn test_verify_checksum_unsigned() {
+    let block = make_header(b"hello.txt", b'0', b"ustar\0", b"00");
+    assert!(Header(&block).verify_checksum());
+  }
+
+  #[test]
+  fn test_verify_checksum_signed() {
+    // Bytes >= 0x80 make the signed and unsigned sums differ.
+    let mut block = make_header("héllo.txt".as_bytes(), b'0', b"ustar\0", b"00");
+    let header = Header(&block);
+    assert_ne!(header.signed_checksum(), header.unsigned_checksum() as i64);
+    let checksum = header.signed_checksum();
+    block[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    assert!(Header(&block).verify_checksum());
+  }
+
+  #[test]
+  fn test_verify_checksum_mismatch() {
+    let mut block = make_header(b"hello.txt", b'0', b"ustar\0", b"00");
+    block[0] = b'j';
+    assert!(!Header(&block).verify_checksum());
+  }
+
+  #[test]
+  fn test_verify_checksum_malformed() {
+    let mut block = make_header(b"hello.txt", b'0', b"ustar\0", b"00");
+    block[148..156].copy_from_slice(b"garbage!");
+    assert!(!Header(&block).verify_checksum());
+  }
+
+  #[test]
+  fn test_format() {
+    let v7 = make_header(b"a", b'0', b"\0\0\0\0\0\0", b"\0\0");
+    let ustar = make_header(b"a", b'0', b"ustar\0", b"00");
+    let gnu = make_header(b"a", b'0', b"ustar ", b" \0");
+    let pax_local = make_header(b"a", b'x', b"ustar\0", b"00");
+    let pax_global = make_header(b"a", b'g', b"ustar\0", b"00");
+    assert_eq!(Header(&v7).format(), Format::V7);
+    assert_eq!(Header(&ustar).format(), Format::Ustar);
+    assert_eq!(Header(&gnu).format(), Format::Gnu);
+    assert_eq!(Header(&pax_local).format(), Format::Pax);
+    assert_eq!(Header(&pax_global).format(), Format::Pax);
+  }
+}
@@ -0,0 +1,342 @@
+// Parsing of the records in pax extended headers: the content of x records,
+// which apply to the next member, and of g records, which apply to every
+// member after them.
+//
+// Each record has the form "<length> <key>=<value>\n", where the length is
+// in decimal and counts the whole record, including the length itself and
+// the newline. Values are UTF-8 text, except for those of a few keys (such as
+// xattrs) that may hold arbitrary bytes, so both keys and values are left as
+// byte strings.
+
+use std::fmt;
+
+// The offset is that of the byte within the extended header's content at
+// which the problem was detected.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParsePaxError(pub usize, pub &'static str);
+
+impl fmt::Display for ParsePaxError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "malformed pax record at byte {} of extended header: {}", self.0, self.1)
+  }
+}
+
+impl std::error::Error for ParsePaxError {}
+
+// Iterates over the (key, value) records of an extended header's content.
+// Stops at the first malformed record, after yielding an error for it.
+#[derive(Clone, Debug)]
+pub struct Records<'a> {
+  content: &'a [u8],
+  offset: usize,
+}
+
+pub fn records(content: &[u8]) -> Records<'_> {
+  Records { content, offset: 0 }
+}
+
+impl<'a> Records<'a> {
+  // The offset within the content of the next record.
+  pub fn offset(&self) -> usize {
+    self.offset
+  }
+}
+
+impl<'a> Iterator for Records<'a> {
+  type Item = Result<(&'a [u8], &'a [u8]), ParsePaxError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let rest = &self.content[self.offset..];
+    // Some writers pad the content with NULs.
+    if rest.iter().all(|&byte| byte == 0) {
+      return None;
+    }
+    let result = parse_record(rest).map_err(|ParsePaxError(offset, msg)| ParsePaxError(self.offset + offset, msg));
+    match result {
+      Ok((len, key, value)) => {
+        self.offset += len;
+        Some(Ok((key, value)))
+      }
+      Err(err) => {
+        self.offset = self.content.len();
+        Some(Err(err))
+      }
+    }
+  }
+}
+
+// Returns the length of the record at the start of input, and its key and value.
+fn parse_record(input: &[u8]) -> Result<(usize, &[u8], &[u8]), ParsePaxError> {
+  let space = input
+    .iter()
+    .position(|&byte| byte == b' ')
+    .ok_or(ParsePaxError(0, "missing record length"))?;
+  let len = parse_decimal(&input[..space])
+    .and_then(|len| usize::try_from(len).ok())
+    .ok_or(ParsePaxError(0, "bad record length"))?;
+  let record = input.get(..len).ok_or(ParsePaxError(input.len(), "record extends past end of header"))?;
+  let Some((b'\n', key_value)) = record.get(space + 1..).and_then(|rest| rest.split_last()) else {
+    return Err(ParsePaxError(len.saturating_sub(1), "record not terminated by a newline"));
+  };
+  let equals = key_value
+    .iter()
+    .position(|&byte| byte == b'=')
+    .ok_or(ParsePaxError(space + 1, "missing '=' in record"))?;
+  if equals == 0 {
+    return Err(ParsePaxError(space + 1, "empty key"));
+  }
+  Ok((len, &key_value[..equals], &key_value[equals + 1..]))
+}
+
+// Parses an unsigned decimal number with no sign or leading whitespace.
+pub fn parse_decimal(input: &[u8]) -> Option<u64> {
+  if input.is_empty() {
+    return None;
+  }
+  input.iter().try_fold(0u64, |accum, &byte| match byte {
+    b'0'..=b'9' => accum.checked_mul(10)?.checked_add(u64::from(byte - b'0')),
+    _ => None,
+  })
+}
+
+// A pax timestamp, which may be negative and have fractional seconds.
+// Normalised so that nanos is less than one second, even for negative times:
+// -1.5 is represented as secs -2 and nanos 500000000.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Timestamp {
+  pub secs: i64,
+  pub nanos: u32,
+}
+
+impl Timestamp {
+  pub fn parse(input: &[u8]) -> Option<Timestamp> {
+    let (negative, input) = match input.split_first() {
+      Some((b'-', rest)) => (true, rest),
+      _ => (false, input),
+    };
+    let (whole, fraction) = match input.iter().position(|&byte| byte == b'.') {
+      Some(dot) => (&input[..dot], &input[dot + 1..]),
+      None => (input, &b""[..]),
+    };
+    let secs = i64::try_from(parse_decimal(whole)?).ok()?;
+    if !fraction.iter().all(u8::is_ascii_digit) {
+      return None;
+    }
+    // Digits beyond nanosecond precision are dropped.
+    let nanos = (0..9).fold(0, |accum, i| accum * 10 + fraction.get(i).map_or(0, |&digit| u32::from(digit - b'0')));
+    Some(match (negative, nanos) {
+      (false, _) => Timestamp { secs, nanos },
+      (true, 0) => Timestamp { secs: -secs, nanos: 0 },
+      (true, _) => Timestamp { secs: -secs - 1, nanos: 1_000_000_000 - nanos },
+    })
+  }
+}
+
+// The recognised contents of an extended header. Records with empty values
+// are significant: in an x record they cancel any value given by a g record
+// for that key, so they are kept in deleted rather than being parsed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PaxHeader<'a> {
+  pub path: Option<&'a [u8]>,
+  pub linkpath: Option<&'a [u8]>,
+  pub size: Option<u64>,
+  pub mtime: Option<Timestamp>,
+  pub uid: Option<u64>,
+  pub gid: Option<u64>,
+  // Extended attributes, from SCHILY.xattr.<name> records, as (name, value).
+  pub xattrs: Vec<(&'a [u8], &'a [u8])>,
+  // Other keys with the SCHILY. prefix (as written by star and GNU tar), with
+  // the prefix removed.
+  pub schily: Vec<(&'a [u8], &'a [u8])>,
+  // Any other records, as (key, value).
+  pub other: Vec<(&'a [u8], &'a [u8])>,
+  pub deleted: Vec<&'a [u8]>,
+}
+
+impl<'a> PaxHeader<'a> {
+  // Where a key appears more than once, the last value applies.
+  pub fn parse(content: &'a [u8]) -> Result<PaxHeader<'a>, ParsePaxError> {
+    let mut header = PaxHeader::default();
+    let mut records = records(content);
+    loop {
+      // Bad values are reported at the start of the record holding them.
+      let offset = records.offset;
+      let Some(record) = records.next() else { break };
+      let (key, value) = record?;
+      let bad_value = |msg| ParsePaxError(offset, msg);
+      if value.is_empty() {
+        header.deleted.push(key);
+        continue;
+      }
+      match key {
+        b"path" => header.path = Some(value),
+        b"linkpath" => header.linkpath = Some(value),
+        b"size" => header.size = Some(parse_decimal(value).ok_or(bad_value("bad size"))?),
+        b"mtime" => header.mtime = Some(Timestamp::parse(value).ok_or(bad_value("bad mtime"))?),
+        b"uid" => header.uid = Some(parse_decimal(value).ok_or(bad_value("bad uid"))?),
+        b"gid" => header.gid = Some(parse_decimal(value).ok_or(bad_value("bad gid"))?),
+        _ => match (key.strip_prefix(b"SCHILY.xattr."), key.strip_prefix(b"SCHILY.")) {
+          (Some(name), _) => header.xattrs.push((name, value)),
+          (None, Some(key)) => header.schily.push((key, value)),
+          (None, None) => header.other.push((key, value)),
+        },
+      }
+    }
+    Ok(header)
+  }
+}
+
+// The size given by an extended header, if any, for working out the length
+// of the member it applies to. Only the size record is parsed, so that bad
+// values for other keys don't prevent splitting the archive. Some(None)
+// means that the header cancels any size given by a g record.
+pub fn size(content: &[u8]) -> Result<Option<Option<u64>>, ParsePaxError> {
+  let mut size = None;
+  let mut records = records(content);
+  loop {
+    let offset = records.offset;
+    let Some(record) = records.next() else { break };
+    let (key, value) = record?;
+    if key == b"size" {
+      size = match value {
+        b"" => Some(None),
+        _ => Some(Some(parse_decimal(value).ok_or(ParsePaxError(offset, "bad size"))?)),
+      };
+    }
+  }
+  Ok(size)
+}
+
+// Builds the content of an extended header from (key, value) pairs.
+pub fn build_records(records: &[(&[u8], &[u8])]) -> Vec<u8> {
+  let mut out = Vec::new();
+  for (key, value) in records {
+    // The length includes its own digits, so may need another digit once
+    // they are counted.
+    let unprefixed = key.len() + value.len() + 3;
+    let mut len = unprefixed + unprefixed.to_string().len();
+    if len.to_string().len() + unprefixed != len {
+      len += 1;
+    }
+    out.extend_from_slice(format!("{len} ").as_bytes());
+    out.extend_from_slice(key);
+    out.push(b'=');
+    out.extend_from_slice(value);
+    out.push(b'\n');
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_records() {
+    let content = b"30 mtime=1700878794.123456789\n18 path=some/file\n10 uname=\n\0\0\0";
+    let records: Vec<_> = records(content).collect();
+    assert_eq!(
+      records,
+      [Ok((&b"mtime"[..], &b"1700878794.123456789"[..])), Ok((b"path", b"some/file")), Ok((b"uname", b""))],
+    );
+    assert_eq!(super::records(b"").count(), 0);
+  }
+
+  #[test]
+  fn test_value_with_newlines() {
+    let content = b"25 SCHILY.xattr.a=x\ny=z\n\n";
+    let header = PaxHeader::parse(content).unwrap();
+    assert_eq!(header.xattrs, [(&b"a"[..], &b"x\ny=z\n"[..])]);
+  }
+
+  #[test]
+  fn test_malformed_records() {
+    let first_error = |content: &[u8]| records(content).find_map(Result::err);
+    assert_eq!(first_error(b"path=x\n"), Some(ParsePaxError(0, "missing record length")));
+    assert_eq!(first_error(b"1x path=x\n"), Some(ParsePaxError(0, "bad record length")));
+    assert_eq!(first_error(b"11 path=x\n"), Some(ParsePaxError(10, "record extends past end of header")));
+    assert_eq!(first_error(b"12 path=xy\n10 path=x\n"), Some(ParsePaxError(11, "record not terminated by a newline")));
+    assert_eq!(first_error(b"10 path=x\n8 pathx\n"), Some(ParsePaxError(12, "missing '=' in record")));
+    assert_eq!(first_error(b"6 =xy\n"), Some(ParsePaxError(2, "empty key")));
+    // Iteration stops after an error.
+    assert_eq!(records(b"x\n10 path=x\n").count(), 1);
+  }
+
+  #[test]
+  fn test_parse_header() {
+    let content = build_records(&[
+      (b"path", "sömé/long/path".as_bytes()),
+      (b"linkpath", b"target"),
+      (b"size", b"8589934592"),
+      (b"mtime", b"1700878794.5"),
+      (b"uid", b"1000"),
+      (b"gid", b"100"),
+      (b"SCHILY.xattr.user.comment", b"\xff\x00binary"),
+      (b"SCHILY.fflags", b"nodump"),
+      (b"SCHILY.dev", b"2049"),
+      (b"atime", b"1700878794"),
+      (b"uname", b""),
+      (b"uid", b"1001"),
+    ]);
+    let header = PaxHeader::parse(&content).unwrap();
+    assert_eq!(
+      header,
+      PaxHeader {
+        path: Some("sömé/long/path".as_bytes()),
+        linkpath: Some(b"target"),
+        size: Some(8 << 30),
+        mtime: Some(Timestamp { secs: 1700878794, nanos: 500_000_000 }),
+        uid: Some(1001),
+        gid: Some(100),
+        xattrs: vec![(&b"user.comment"[..], &b"\xff\x00binary"[..])],
+        schily: vec![(&b"fflags"[..], &b"nodump"[..]), (b"dev", b"2049")],
+        other: vec![(&b"atime"[..], &b"1700878794"[..])],
+        deleted: vec![&b"uname"[..]],
+      }
+    );
+  }
+
+  #[test]
+  fn test_bad_values() {
+    let content = build_records(&[(b"path", b"a"), (b"size", b"12x")]);
+    assert_eq!(PaxHeader::parse(&content), Err(ParsePaxError(9, "bad size")));
+    let content = build_records(&[(b"mtime", b"yesterday")]);
+    assert_eq!(PaxHeader::parse(&content), Err(ParsePaxError(0, "bad mtime")));
+    // Only the size matters for size().
+    assert_eq!(size(&content), Ok(None));
+  }
+
+  #[test]
+  fn test_size() {
+    assert_eq!(size(&build_records(&[(b"path", b"a"), (b"size", b"42")])), Ok(Some(Some(42))));
+    assert_eq!(size(&build_records(&[(b"size", b"42"), (b"size", b"")])), Ok(Some(None)));
+    assert_eq!(size(&build_records(&[(b"path", b"a")])), Ok(None));
+    assert_eq!(size(&build_records(&[(b"path", b"a"), (b"size", b"-1")])), Err(ParsePaxError(9, "bad size")));
+    assert_eq!(size(&build_records(&[(b"size", b"99999999999999999999")])), Err(ParsePaxError(0, "bad size")));
+  }
+
+  #[test]
+  fn test_timestamp() {
+    let parse = |input: &[u8]| Timestamp::parse(input).map(|t| (t.secs, t.nanos));
+    assert_eq!(parse(b"1700878794"), Some((1700878794, 0)));
+    assert_eq!(parse(b"1700878794.123456789123"), Some((1700878794, 123456789)));
+    assert_eq!(parse(b"0.000000001"), Some((0, 1)));
+    assert_eq!(parse(b"12."), Some((12, 0)));
+    assert_eq!(parse(b"-1.5"), Some((-2, 500_000_000)));
+    assert_eq!(parse(b"-7"), Some((-7, 0)));
+    assert_eq!(parse(b""), None);
+    assert_eq!(parse(b".5"), None);
+    assert_eq!(parse(b"1.5e3"), None);
+    assert_eq!(parse(b"+1"), None);
+  }
+
+  #[test]
+  fn test_build_records() {
+    // Lengths straddling a change in the number of digits.
+    for value_len in 0..120 {
+      let value = vec![b'v'; value_len];
+      let content = build_records(&[(b"comment", &value)]);
+      assert_eq!(records(&content).collect::<Vec<_>>(), [Ok((&b"comment"[..], &value[..]))]);
+    }
+  }
+}
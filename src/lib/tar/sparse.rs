@@ -0,0 +1,255 @@
+// Sparse files, whose holes are not stored in the archive. GNU tar records
+// which regions of the file are stored in one of several ways:
+//   - old GNU format: an S member, whose header holds the first four entries
+//     of the map, continued in extension blocks between the header and the
+//     content if the header's isextended flag is set;
+//   - pax format 0.0: GNU.sparse.offset and GNU.sparse.numbytes records in
+//     the member's extended header, alternating;
+//   - pax format 0.1: a GNU.sparse.map record, holding the same numbers
+//     separated by commas;
+//   - pax format 1.0: decimal numbers, one per line, at the start of the
+//     member's content (the entry count, then an offset and length per
+//     entry), padded to a whole number of blocks.
+// In every case, the stored regions are concatenated to form the rest of
+// the content, which is therefore shorter than the file it represents.
+
+use crate::tar::pax::{self, parse_decimal};
+use crate::tar::{parse_numeric, Header};
+
+// The offset is that of the byte (relative to wherever the map was read
+// from) at which the problem was detected.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseSparseError(pub usize, pub &'static str);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SparseEntry {
+  pub offset: u64,
+  pub len: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SparseMap {
+  // Size of the file once its holes are restored.
+  pub real_size: u64,
+  pub entries: Vec<SparseEntry>,
+}
+
+// An extension block following an old GNU sparse header.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SparseExtension<'a>(pub &'a [u8; 512]);
+
+impl<'a> SparseExtension<'a> {
+  // Whether another extension block follows this one.
+  pub fn is_extended(self) -> bool {
+    self.0[504] != 0
+  }
+}
+
+// Parses the 24-byte (offset, length) entries of an old GNU sparse map.
+// Unused entries are zero-filled, and end the map.
+fn parse_entries(entries: &[u8], base_offset: usize, out: &mut Vec<SparseEntry>) -> Result<bool, ParseSparseError> {
+  for (i, entry) in entries.chunks_exact(24).enumerate() {
+    if entry.iter().all(|&byte| byte == 0) {
+      return Ok(false);
+    }
+    let offset = base_offset + 24 * i;
+    let offset_field: [u8; 12] = entry[..12].try_into().unwrap();
+    let len_field: [u8; 12] = entry[12..].try_into().unwrap();
+    out.push(SparseEntry {
+      offset: parse_numeric(offset_field).map_err(|_| ParseSparseError(offset, "bad sparse offset"))?,
+      len: parse_numeric(len_field).map_err(|_| ParseSparseError(offset + 12, "bad sparse length"))?,
+    });
+  }
+  Ok(true)
+}
+
+// Parses the map of an old GNU sparse member from its header and the
+// extension blocks that follow it, which must all be present. Offsets in
+// errors are relative to the start of the header.
+pub fn parse_gnu(header: Header, extensions: &[u8]) -> Result<SparseMap, ParseSparseError> {
+  let real_size = parse_numeric::<12>(header.0[483..495].try_into().unwrap())
+    .map_err(|_| ParseSparseError(483, "bad sparse real size"))?;
+  let mut entries = Vec::new();
+  let mut more = parse_entries(&header.0[386..482], 386, &mut entries)? && header.is_extended();
+  for (i, block) in extensions.chunks(512).enumerate() {
+    if !more {
+      break;
+    }
+    let block: &[u8; 512] = block.try_into().map_err(|_| ParseSparseError(512 * (i + 1), "truncated sparse map"))?;
+    more = parse_entries(&block[..504], 512 * (i + 1), &mut entries)? && SparseExtension(block).is_extended();
+  }
+  Ok(SparseMap { real_size, entries })
+}
+
+// Parses the map of a pax 0.0 or 0.1 sparse member from the records of its
+// extended header. Returns None if the records don't describe a sparse file
+// in either format. Offsets in errors are relative to the content of the
+// extended header.
+pub fn parse_pax_0(records: pax::Records) -> Result<Option<SparseMap>, ParseSparseError> {
+  let mut real_size = None;
+  let mut entries = Vec::new();
+  let mut pending_offset = None;
+  let mut found_map = false;
+  let mut records = records;
+  loop {
+    let record_offset = records.offset();
+    let Some(record) = records.next() else { break };
+    let Ok((key, value)) = record else { return Err(ParseSparseError(record_offset, "malformed pax record")) };
+    let number = || parse_decimal(value).ok_or(ParseSparseError(record_offset, "bad sparse map value"));
+    match key {
+      b"GNU.sparse.size" => real_size = Some(number()?),
+      b"GNU.sparse.offset" => {
+        found_map = true;
+        pending_offset = Some(number()?);
+      }
+      b"GNU.sparse.numbytes" => {
+        let offset = pending_offset.take().ok_or(ParseSparseError(record_offset, "sparse length without offset"))?;
+        entries.push(SparseEntry { offset, len: number()? });
+      }
+      b"GNU.sparse.map" => {
+        found_map = true;
+        entries = parse_numbers(value.split(|&byte| byte == b','))
+          .ok_or(ParseSparseError(record_offset, "bad sparse map value"))?;
+      }
+      _ => {}
+    }
+  }
+  match (real_size, found_map) {
+    (Some(real_size), true) => Ok(Some(SparseMap { real_size, entries })),
+    (None, false) => Ok(None),
+    _ => Err(ParseSparseError(0, "incomplete sparse map")),
+  }
+}
+
+// Parses the map at the start of a pax 1.0 sparse member's content, given
+// the real size from its GNU.sparse.realsize record. Returns the map and the
+// length of its encoding, including padding. Offsets in errors are relative
+// to the start of the content.
+pub fn parse_pax_1(content: &[u8], real_size: u64) -> Result<(SparseMap, usize), ParseSparseError> {
+  let mut lines = content.split_inclusive(|&byte| byte == b'\n');
+  let mut offset = 0;
+  let mut next_number = || {
+    let line = lines.next().filter(|line| line.ends_with(b"\n")).ok_or(ParseSparseError(offset, "truncated sparse map"))?;
+    let number = parse_decimal(&line[..line.len() - 1]).ok_or(ParseSparseError(offset, "bad sparse map value"))?;
+    offset += line.len();
+    Ok::<_, ParseSparseError>(number)
+  };
+  let count = next_number()?;
+  let mut entries = Vec::new();
+  for _ in 0..count {
+    let entry_offset = next_number()?;
+    entries.push(SparseEntry { offset: entry_offset, len: next_number()? });
+  }
+  let len = offset.next_multiple_of(512);
+  if len > content.len() {
+    return Err(ParseSparseError(content.len(), "truncated sparse map"));
+  }
+  Ok((SparseMap { real_size, entries }, len))
+}
+
+fn parse_numbers<'a>(mut fields: impl Iterator<Item = &'a [u8]>) -> Option<Vec<SparseEntry>> {
+  let mut entries = Vec::new();
+  while let Some(offset) = fields.next() {
+    entries.push(SparseEntry { offset: parse_decimal(offset)?, len: parse_decimal(fields.next()?)? });
+  }
+  Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tar::pax::{build_records, records};
+  use crate::util::testing::gnu_sparse_member;
+
+  fn entries(pairs: &[(u64, u64)]) -> Vec<SparseEntry> {
+    pairs.iter().map(|&(offset, len)| SparseEntry { offset, len }).collect()
+  }
+
+  // The header and extension blocks of an old GNU sparse member with the given entries.
+  fn gnu_sparse(pairs: &[(u64, u64)], real_size: u64) -> ([u8; 512], Vec<u8>) {
+    let member = gnu_sparse_member(b"sparse", pairs, real_size);
+    let data_len = Header(member[..512].try_into().unwrap()).content_len().unwrap().next_multiple_of(512);
+    (member[..512].try_into().unwrap(), member[512..member.len() - data_len as usize].to_vec())
+  }
+
+  #[test]
+  fn test_gnu() {
+    let (block, extensions) = gnu_sparse(&[(0, 10), (100, 20)], 1000);
+    assert!(!Header(&block).is_extended());
+    let map = parse_gnu(Header(&block), &extensions).unwrap();
+    assert_eq!(map, SparseMap { real_size: 1000, entries: entries(&[(0, 10), (100, 20)]) });
+  }
+
+  #[test]
+  fn test_gnu_extended() {
+    let pairs: Vec<_> = (0..50).map(|i| (i * 1000, 512)).collect();
+    let (block, extensions) = gnu_sparse(&pairs, 50_000);
+    assert!(Header(&block).is_extended());
+    assert_eq!(extensions.len(), 3 * 512);
+    let map = parse_gnu(Header(&block), &extensions).unwrap();
+    assert_eq!(map, SparseMap { real_size: 50_000, entries: entries(&pairs) });
+    // Exactly filling the header leaves no extension blocks.
+    let (block, extensions) = gnu_sparse(&pairs[..4], 50_000);
+    assert!(extensions.is_empty());
+    assert_eq!(parse_gnu(Header(&block), &extensions).unwrap().entries, entries(&pairs[..4]));
+    let (block, extensions) = gnu_sparse(&pairs, 50_000);
+    assert_eq!(parse_gnu(Header(&block), &extensions[..1000]), Err(ParseSparseError(1024, "truncated sparse map")));
+  }
+
+  #[test]
+  fn test_gnu_malformed() {
+    let (mut block, extensions) = gnu_sparse(&[(0, 10), (100, 20)], 1000);
+    block[410..422].copy_from_slice(b"garbage!!!!\0");
+    assert_eq!(parse_gnu(Header(&block), &extensions), Err(ParseSparseError(410, "bad sparse offset")));
+  }
+
+  #[test]
+  fn test_pax_0_0() {
+    let content = build_records(&[
+      (b"GNU.sparse.size", b"1300000"),
+      (b"GNU.sparse.numblocks", b"2"),
+      (b"GNU.sparse.offset", b"0"),
+      (b"GNU.sparse.numbytes", b"4096"),
+      (b"GNU.sparse.offset", b"1300000"),
+      (b"GNU.sparse.numbytes", b"0"),
+      (b"path", b"sparse.bin"),
+    ]);
+    let map = parse_pax_0(records(&content)).unwrap();
+    assert_eq!(map, Some(SparseMap { real_size: 1300000, entries: entries(&[(0, 4096), (1300000, 0)]) }));
+  }
+
+  #[test]
+  fn test_pax_0_1() {
+    let content = build_records(&[
+      (b"GNU.sparse.size", b"1300000"),
+      (b"GNU.sparse.name", b"sparse.bin"),
+      (b"GNU.sparse.map", b"0,4096,98304,4096,1300000,0"),
+    ]);
+    let map = parse_pax_0(records(&content)).unwrap();
+    assert_eq!(map, Some(SparseMap { real_size: 1300000, entries: entries(&[(0, 4096), (98304, 4096), (1300000, 0)]) }));
+  }
+
+  #[test]
+  fn test_pax_0_malformed() {
+    assert_eq!(parse_pax_0(records(&build_records(&[(b"path", b"a")]))), Ok(None));
+    let content = build_records(&[(b"GNU.sparse.size", b"10"), (b"GNU.sparse.map", b"0,4096,98304")]);
+    assert_eq!(parse_pax_0(records(&content)), Err(ParseSparseError(22, "bad sparse map value")));
+    let content = build_records(&[(b"GNU.sparse.size", b"10"), (b"GNU.sparse.numbytes", b"1")]);
+    assert_eq!(parse_pax_0(records(&content)), Err(ParseSparseError(22, "sparse length without offset")));
+    let content = build_records(&[(b"GNU.sparse.size", b"10")]);
+    assert_eq!(parse_pax_0(records(&content)), Err(ParseSparseError(0, "incomplete sparse map")));
+  }
+
+  #[test]
+  fn test_pax_1() {
+    let mut content = b"3\n0\n4096\n98304\n4096\n1300000\n0\n".to_vec();
+    content.resize(512, 0);
+    content.extend_from_slice(&[b'x'; 8192]);
+    let (map, len) = parse_pax_1(&content, 1300000).unwrap();
+    assert_eq!(map, SparseMap { real_size: 1300000, entries: entries(&[(0, 4096), (98304, 4096), (1300000, 0)]) });
+    assert_eq!(len, 512);
+    assert_eq!(parse_pax_1(b"2\n0\n4096\n98304\n", 0), Err(ParseSparseError(15, "truncated sparse map")));
+    assert_eq!(parse_pax_1(b"1\n0\nx\n", 0), Err(ParseSparseError(4, "bad sparse map value")));
+    assert_eq!(parse_pax_1(b"1\n0\n1\n", 0), Err(ParseSparseError(6, "truncated sparse map")));
+  }
+}
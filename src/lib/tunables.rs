@@ -1,2 +1,27 @@
+// Defaults for, and bounds on, the shingleprinting parameters.
 pub const SHINGLE_LEN: usize = 16; // bytes
+pub const MAX_SHINGLE_LEN: usize = 64; // bytes
 pub const SHINGLEPRINT_FEATURES: usize = 32;
+pub const MIN_SHINGLEPRINT_FEATURES: usize = LSH_BANDS * LSH_ROWS;
+pub const MAX_SHINGLEPRINT_FEATURES: usize = 64;
+// Split of shingleprint hashes into bands for locality-sensitive hashing.
+// More rows per band make candidates more precise; more bands improve recall.
+pub const LSH_BANDS: usize = 8;
+pub const LSH_ROWS: usize = 2;
+const _: () = assert!(SHINGLE_LEN <= MAX_SHINGLE_LEN);
+const _: () = assert!(MIN_SHINGLEPRINT_FEATURES <= SHINGLEPRINT_FEATURES);
+const _: () = assert!(SHINGLEPRINT_FEATURES <= MAX_SHINGLEPRINT_FEATURES);
+// Bound on the number of entries of one LSH bucket examined per query.
+pub const LSH_MAX_BUCKET_SCAN: usize = 256;
+// Bounds on the length of frame heads and tails that are shingleprinted,
+// which is otherwise derived from the target compressor's window.
+pub const MIN_HEAD_AND_TAIL_LEN: usize = 4 * 1024; // bytes
+pub const MAX_HEAD_AND_TAIL_LEN: usize = 1024 * 1024; // bytes
+pub const N_SHINGLEPRINTING_THREADS: usize = 4;
+// Soft limit on memory used to buffer frame heads and tails when reading from a pipe.
+pub const INGRESS_BUFFER_MEMORY_TARGET: usize = 64 * 1024 * 1024; // bytes
+// Number of most-similar successors retained for each frame when ordering.
+pub const MAX_CANDIDATES_PER_FRAME: usize = 8;
+// Number of recently placed frames whose tails are compared against
+// candidate successors when ordering.
+pub const MAX_RECENT_FRAMES: usize = 32;
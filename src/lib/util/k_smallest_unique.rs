@@ -4,28 +4,34 @@ use arrayvec::ArrayVec;
 // monomorphisation of this function and then discovers it can't inline it
 // into both the portable and SSE-enabled contexts, leading to one of those
 // shingleprint implementations being under-optimised.
+// Returns at most k elements, where k may not exceed the capacity K.
 #[inline(always)]
-pub fn k_smallest_unique<T, const K: usize>(mut values: impl Iterator<Item = T>) -> ArrayVec<T, K>
+pub fn k_smallest_unique<T, const K: usize>(mut values: impl Iterator<Item = T>, k: usize) -> ArrayVec<T, K>
 where
   T: Ord,
 {
+  assert!(k <= K);
   // Invariant: elements are sorted in ascending order.
   let mut working_set = ArrayVec::new();
+  if k == 0 {
+    return working_set;
+  }
   for candidate in &mut values {
-    debug_assert!(working_set.len() < K);
+    debug_assert!(working_set.len() < k);
     if let Err(insert_idx) = working_set.binary_search(&candidate) {
       working_set.insert(insert_idx, candidate);
-      if working_set.len() == K {
+      if working_set.len() == k {
         break
       }
     }
   }
-  for candidate in values {
-    debug_assert_eq!(working_set.len(), K);
-    if candidate < *working_set.last().unwrap() {
-      if let Err(insert_idx) = working_set.binary_search(&candidate) {
-        working_set.truncate(K-1);
-        working_set.insert(insert_idx, candidate);
+  if working_set.len() == k {
+    for candidate in values {
+      if candidate < *working_set.last().unwrap() {
+        if let Err(insert_idx) = working_set.binary_search(&candidate) {
+          working_set.truncate(k-1);
+          working_set.insert(insert_idx, candidate);
+        }
       }
     }
   }
@@ -39,10 +45,13 @@ mod tests {
   #[test]
   fn test() {
     let input = b"The quick brown fox jumps over the lazy dog."
-      .into_iter()
+      .iter()
       .copied();
-    let got = k_smallest_unique::<_, 12>(input);
-    const EXPECTED: &'static [u8] = b" .Tabcdefghi";
+    let got = k_smallest_unique::<_, 12>(input.clone(), 12);
+    const EXPECTED: &[u8] = b" .Tabcdefghi";
     assert_eq!(got.as_slice(), EXPECTED);
+    let got = k_smallest_unique::<_, 12>(input.clone(), 5);
+    assert_eq!(got.as_slice(), &EXPECTED[..5]);
+    assert!(k_smallest_unique::<_, 12>(input, 0).is_empty());
   }
 }
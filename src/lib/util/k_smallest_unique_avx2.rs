@@ -0,0 +1,155 @@
+use arrayvec::ArrayVec;
+use core::arch::x86_64::*;
+
+// Equivalent to k_smallest_unique::<u32, 32>, but keeps the working set in
+// AVX2 registers, unordered. Membership tests compare against all 32 elements
+// at once, and only the rare candidates smaller than the working set's
+// maximum need testing once it is full.
+// Undefined behaviour if the processor doesn't support the avx2 feature.
+#[inline]
+#[target_feature(enable = "avx2")]
+pub unsafe fn k_smallest_unique_avx2(mut values: impl Iterator<Item = u32>) -> ArrayVec<u32, 32> {
+  // Convention: in a __m256i, lane 0 is the rightmost / least-significant lane.
+
+  struct WorkingSet {
+    data: [__m256i; 4],
+    // All ones in the lanes of data that hold an element; all zeroes otherwise.
+    popmask: [__m256i; 4],
+    npop: usize,
+  }
+
+  impl WorkingSet {
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn new() -> WorkingSet {
+      let zero = _mm256_setzero_si256();
+      WorkingSet { data: [zero; 4], popmask: [zero; 4], npop: 0 }
+    }
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn contains(&self, elem_bcast: __m256i) -> bool {
+      (
+        _mm256_testz_si256(_mm256_cmpeq_epi32(self.data[0], elem_bcast), self.popmask[0]) &
+        _mm256_testz_si256(_mm256_cmpeq_epi32(self.data[1], elem_bcast), self.popmask[1]) &
+        _mm256_testz_si256(_mm256_cmpeq_epi32(self.data[2], elem_bcast), self.popmask[2]) &
+        _mm256_testz_si256(_mm256_cmpeq_epi32(self.data[3], elem_bcast), self.popmask[3])
+      ) == 0
+    }
+    // Places the element in the first unpopulated lane.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn insert(&mut self, elem_bcast: __m256i) {
+      debug_assert!(self.npop < 32);
+      for i in 0..4 {
+        let populated = _mm256_movemask_ps(_mm256_castsi256_ps(self.popmask[i])) as u32;
+        if populated != 0xff {
+          let lane = populated.trailing_ones() as i32;
+          let lanemask = _mm256_cmpeq_epi32(_mm256_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7), _mm256_set1_epi32(lane));
+          self.data[i] = _mm256_blendv_epi8(self.data[i], elem_bcast, lanemask);
+          self.popmask[i] = _mm256_or_si256(self.popmask[i], lanemask);
+          self.npop += 1;
+          return;
+        }
+      }
+      unreachable!();
+    }
+    // The element must be present.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn remove(&mut self, elem_bcast: __m256i) {
+      debug_assert!(self.npop > 0);
+      for i in 0..4 {
+        let eq = _mm256_and_si256(_mm256_cmpeq_epi32(self.data[i], elem_bcast), self.popmask[i]);
+        self.popmask[i] = _mm256_andnot_si256(eq, self.popmask[i]);
+      }
+      self.npop -= 1;
+    }
+    // Only meaningful when the working set is full.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn max(&self) -> u32 {
+      debug_assert_eq!(self.npop, 32);
+      let max = _mm256_max_epu32(
+        _mm256_max_epu32(self.data[0], self.data[1]),
+        _mm256_max_epu32(self.data[2], self.data[3]),
+      );
+      let mut lanes = [0u32; 8];
+      _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, max);
+      lanes.into_iter().max().unwrap()
+    }
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn into_sorted(self) -> ArrayVec<u32, 32> {
+      let mut data = [0u32; 32];
+      let mut popmask = [0u32; 32];
+      for i in 0..4 {
+        _mm256_storeu_si256(data[i * 8..].as_mut_ptr() as *mut __m256i, self.data[i]);
+        _mm256_storeu_si256(popmask[i * 8..].as_mut_ptr() as *mut __m256i, self.popmask[i]);
+      }
+      let mut result: ArrayVec<u32, 32> =
+        data.into_iter().zip(popmask).filter(|&(_, populated)| populated != 0).map(|(elem, _)| elem).collect();
+      result.sort_unstable();
+      result
+    }
+  }
+
+  let mut working_set = WorkingSet::new();
+  for candidate in &mut values {
+    debug_assert!(working_set.npop < 32);
+    let candidate_bcast = _mm256_set1_epi32(candidate as i32);
+    if !working_set.contains(candidate_bcast) {
+      working_set.insert(candidate_bcast);
+      if working_set.npop == 32 {
+        break;
+      }
+    }
+  }
+  if working_set.npop == 32 {
+    let mut max = working_set.max();
+    for candidate in values {
+      if candidate < max {
+        let candidate_bcast = _mm256_set1_epi32(candidate as i32);
+        if !working_set.contains(candidate_bcast) {
+          working_set.remove(_mm256_set1_epi32(max as i32));
+          working_set.insert(candidate_bcast);
+          max = working_set.max();
+        }
+      }
+    }
+  }
+  working_set.into_sorted()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::k_smallest_unique::k_smallest_unique;
+  use crate::util::testing::random_bytes;
+
+  fn check(values: &[u32]) {
+    let expected = k_smallest_unique::<_, 32>(values.iter().copied(), 32);
+    let got = unsafe { k_smallest_unique_avx2(values.iter().copied()) };
+    assert_eq!(got, expected, "{values:?}");
+  }
+
+  #[test]
+  fn test_against_scalar() {
+    if !is_x86_feature_detected!("avx2") {
+      return;
+    }
+    check(&[]);
+    check(&[7]);
+    check(&[0, u32::MAX, 0, u32::MAX]);
+    check(&(0..100).rev().collect::<Vec<_>>());
+    check(&(0..100).collect::<Vec<_>>());
+    check(&[5; 100]);
+    for seed in 0..200 {
+      let bytes = random_bytes(2 * seed + 1, 4 * (seed as usize * 7 % 500));
+      let values: Vec<u32> = bytes.chunks_exact(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect();
+      check(&values);
+      // With plenty of duplicates, and values near the top of the range.
+      check(&values.iter().map(|v| v % 50).collect::<Vec<_>>());
+      check(&values.iter().map(|v| u32::MAX - v % 40).collect::<Vec<_>>());
+    }
+  }
+}
@@ -1 +1,5 @@
 pub mod k_smallest_unique;
+#[cfg(target_arch = "x86_64")]
+pub mod k_smallest_unique_avx2;
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,90 @@
+// Helpers for building synthetic inputs and tar archives in tests.
+
+use crate::tar::{format_numeric, Header};
+
+pub use crate::tar::END_OF_ARCHIVE;
+
+// Builds a ustar header block with a valid checksum.
+pub fn header(name: &[u8], type_flag: u8, content_len: u64) -> [u8; 512] {
+  let mut block = [0u8; 512];
+  block[..name.len()].copy_from_slice(name);
+  block[100..108].copy_from_slice(b"0000644\0");
+  block[108..116].copy_from_slice(b"0001750\0");
+  block[116..124].copy_from_slice(b"0000144\0");
+  block[124..136].copy_from_slice(format!("{content_len:011o}\0").as_bytes());
+  block[136..148].copy_from_slice(b"14530254712\0");
+  block[156] = type_flag;
+  block[257..263].copy_from_slice(b"ustar\0");
+  block[263..265].copy_from_slice(b"00");
+  set_checksum(&mut block);
+  block
+}
+
+fn set_checksum(block: &mut [u8; 512]) {
+  let checksum = Header(block).unsigned_checksum();
+  block[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+}
+
+// Builds a header block followed by the given content, padded to a multiple
+// of the block size.
+pub fn member(name: &[u8], type_flag: u8, content: &[u8]) -> Vec<u8> {
+  let mut out = header(name, type_flag, content.len() as u64).to_vec();
+  out.extend_from_slice(content);
+  out.resize(out.len().next_multiple_of(512), 0);
+  out
+}
+
+// As member, but with the given mode and link target, as for directories
+// and links.
+pub fn entry(name: &[u8], type_flag: u8, mode: u32, linkname: &[u8], content: &[u8]) -> Vec<u8> {
+  let mut out = member(name, type_flag, content);
+  let block: &mut [u8; 512] = (&mut out[..512]).try_into().unwrap();
+  block[100..108].copy_from_slice(format!("{mode:07o}\0").as_bytes());
+  block[157..157 + linkname.len()].copy_from_slice(linkname);
+  set_checksum(block);
+  out
+}
+
+// Builds an old GNU sparse member whose map has the given (offset, len)
+// entries, followed by random data for them. Entries beyond the first four
+// spill into extension blocks.
+pub fn gnu_sparse_member(name: &[u8], entries: &[(u64, u64)], real_size: u64) -> Vec<u8> {
+  let put_entry = |block: &mut [u8], i: usize, (offset, len): (u64, u64)| {
+    block[24 * i..24 * i + 12].copy_from_slice(&format_numeric::<12>(offset));
+    block[24 * i + 12..24 * i + 24].copy_from_slice(&format_numeric::<12>(len));
+  };
+  let data_len: u64 = entries.iter().map(|&(_, len)| len).sum();
+  let mut block = header(name, b'S', data_len);
+  for (i, &entry) in entries.iter().take(4).enumerate() {
+    put_entry(&mut block[386..482], i, entry);
+  }
+  block[482] = u8::from(entries.len() > 4);
+  block[483..495].copy_from_slice(&format_numeric::<12>(real_size));
+  set_checksum(&mut block);
+  let mut out = block.to_vec();
+  let rest = entries.get(4..).unwrap_or_default();
+  for (n, chunk) in rest.chunks(21).enumerate() {
+    let mut extension = [0u8; 512];
+    for (i, &entry) in chunk.iter().enumerate() {
+      put_entry(&mut extension, i, entry);
+    }
+    extension[504] = u8::from((n + 1) * 21 < rest.len());
+    out.extend_from_slice(&extension);
+  }
+  out.extend_from_slice(&random_bytes(entries.len() as u64, data_len as usize));
+  out.resize(out.len().next_multiple_of(512), 0);
+  out
+}
+
+// Deterministic xorshift generator, so that synthetic inputs are reproducible.
+pub fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+  let mut state = seed | 1;
+  (0..len)
+    .map(|_| {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      state as u8
+    })
+    .collect()
+}
@@ -0,0 +1,274 @@
+// Checking that a crushed archive is faithful to the original it was produced
+// from: that it holds exactly the same members, and that restoring it
+// recreates the original byte for byte. Both are compared uncompressed.
+
+use crate::ingress::MapStrategy;
+use crate::restore;
+use crate::tar::Member;
+use crate::Error;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+use xxhash_rust::xxh3::xxh3_128;
+
+// The first way found in which the crushed archive differs from the original.
+// Members are identified by their names and the offsets of their frames.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Divergence {
+  // A member of the original whose frame isn't in the crushed archive, but
+  // another member of the same name is.
+  ChangedMember { name: Vec<u8>, original_offset: usize, crushed_offset: usize },
+  // A member of the original with nothing corresponding to it in the crushed
+  // archive.
+  MissingMember { name: Vec<u8>, original_offset: usize },
+  // A member of the crushed archive with nothing corresponding to it in the
+  // original.
+  ExtraMember { name: Vec<u8>, crushed_offset: usize },
+  // The offset of the first byte at which the restored archive differs from
+  // the uncompressed original (which may be the end of the shorter of the
+  // two).
+  RestoredBytes(usize),
+}
+
+impl fmt::Display for Divergence {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Divergence::ChangedMember { name, original_offset, crushed_offset } => write!(
+        f,
+        "member \"{}\" at byte {original_offset} of the original differs from the one at byte {crushed_offset} of \
+         the crushed archive",
+        String::from_utf8_lossy(name)
+      ),
+      Divergence::MissingMember { name, original_offset } => write!(
+        f,
+        "member \"{}\" at byte {original_offset} of the original is missing from the crushed archive",
+        String::from_utf8_lossy(name)
+      ),
+      Divergence::ExtraMember { name, crushed_offset } => write!(
+        f,
+        "member \"{}\" at byte {crushed_offset} of the crushed archive is not in the original",
+        String::from_utf8_lossy(name)
+      ),
+      Divergence::RestoredBytes(offset) => write!(f, "restored archive differs from the uncompressed original at byte {offset}"),
+    }
+  }
+}
+
+// Checks that crushed is a faithful crushing of original, returning the first
+// divergence found if not. Only the members before each archive's
+// end-of-archive marker are compared, as only those are seen by tar
+// implementations; anything after the original's marker that the crushed
+// archive has moved before its own is an extra member.
+pub fn verify(original: &[u8], crushed: &[u8]) -> Result<(), Error> {
+  let crushed_len = restore::crushed_len(crushed).map_err(in_crushed)?;
+  let original_frames = MapStrategy::new(original).frame_bounds()?;
+  let crushed_frames = MapStrategy::new(&crushed[..crushed_len]).frame_bounds().map_err(in_crushed)?;
+  compare_members(original, &original_frames, crushed, &crushed_frames).map_err(Error::Divergence)?;
+
+  let mut restored = Comparison { expected: original, offset: 0, divergence: None };
+  restore::restore(crushed, &mut restored).map_err(in_crushed)?;
+  if restored.divergence.is_none() && restored.offset != original.len() {
+    restored.divergence = Some(restored.offset);
+  }
+  match restored.divergence {
+    Some(offset) => Err(Error::Divergence(Divergence::RestoredBytes(offset))),
+    None => Ok(()),
+  }
+}
+
+// Tells malformations of the crushed archive apart from those of the original.
+fn in_crushed(err: Error) -> Error {
+  match err {
+    Error::MalformedInput(malformed) => Error::MalformedCrushed(malformed),
+    err => err,
+  }
+}
+
+// Checks that the two sets of frames hold the same members, with the same
+// headers and content, the same number of times. Divergences are reported in
+// the order of the original archive, then of the crushed one.
+fn compare_members(
+  original: &[u8],
+  original_frames: &[Range<usize>],
+  crushed: &[u8],
+  crushed_frames: &[Range<usize>],
+) -> Result<(), Divergence> {
+  let mut unmatched: HashMap<u128, VecDeque<usize>> = HashMap::new();
+  for (i, bounds) in crushed_frames.iter().enumerate() {
+    unmatched.entry(xxh3_128(&crushed[bounds.clone()])).or_default().push_back(i);
+  }
+  let mut matched = vec![false; crushed_frames.len()];
+  let mut first_missing = None;
+  for bounds in original_frames {
+    match unmatched.get_mut(&xxh3_128(&original[bounds.clone()])).and_then(VecDeque::pop_front) {
+      Some(i) => matched[i] = true,
+      None => first_missing = first_missing.or(Some(bounds.clone())),
+    }
+  }
+  let mut unmatched_crushed =
+    crushed_frames.iter().zip(matched).filter(|&(_, matched)| !matched).map(|(bounds, _)| bounds.clone());
+  if let Some(bounds) = first_missing {
+    let name = member_name(&original[bounds.clone()]);
+    let original_offset = bounds.start;
+    return Err(match unmatched_crushed.find(|bounds| member_name(&crushed[bounds.clone()]) == name) {
+      Some(bounds) => Divergence::ChangedMember { name, original_offset, crushed_offset: bounds.start },
+      None => Divergence::MissingMember { name, original_offset },
+    });
+  }
+  match unmatched_crushed.next() {
+    Some(bounds) => {
+      Err(Divergence::ExtraMember { name: member_name(&crushed[bounds.clone()]), crushed_offset: bounds.start })
+    }
+    None => Ok(()),
+  }
+}
+
+fn member_name(frame: &[u8]) -> Vec<u8> {
+  Member::parse(frame).map(|member| member.name).unwrap_or_default()
+}
+
+// Compares what's written to it with the expected bytes, noting the offset of
+// the first difference.
+struct Comparison<'a> {
+  expected: &'a [u8],
+  offset: usize,
+  divergence: Option<usize>,
+}
+
+impl<'a> Write for Comparison<'a> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if self.divergence.is_none() {
+      let expected = self.expected.get(self.offset..).unwrap_or_default();
+      let same = buf.iter().zip(expected).take_while(|(a, b)| a == b).count();
+      if same < buf.len() {
+        self.divergence = Some(self.offset + same);
+      }
+    }
+    self.offset += buf.len();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crush::crush;
+  use crate::error::Malformation;
+  use crate::params::Params;
+  use crate::util::testing::{header, member, random_bytes, END_OF_ARCHIVE};
+
+  fn archive() -> Vec<u8> {
+    [
+      member(b"a.txt", b'0', b"The quick brown fox jumps over the lazy dog."),
+      member(b"c.txt", b'0', b"The quick brown fox jumps over the lazy cat."),
+      member(b"b.bin", b'0', &random_bytes(1, 10_000)),
+      member(b"d.txt", b'0', b"The quick brown fox jumps over the lazy dog."),
+      END_OF_ARCHIVE.to_vec(),
+    ]
+    .concat()
+  }
+
+  fn crushed(original: &[u8]) -> Vec<u8> {
+    let mut crushed = Vec::new();
+    crush(&mut MapStrategy::new(original), &mut crushed, &Params::default()).unwrap();
+    crushed
+  }
+
+  fn divergence(original: &[u8], crushed: &[u8]) -> Divergence {
+    match verify(original, crushed) {
+      Err(Error::Divergence(divergence)) => divergence,
+      result => panic!("unexpected result: {result:?}"),
+    }
+  }
+
+  // The offset within the crushed archive of the given header.
+  fn crushed_offset(crushed: &[u8], header: &[u8; 512]) -> usize {
+    crushed.chunks(512).position(|block| block == header).unwrap() * 512
+  }
+
+  #[test]
+  fn test_verify() {
+    let original = archive();
+    verify(&original, &crushed(&original)).unwrap();
+    verify(b"", &crushed(b"")).unwrap();
+  }
+
+  #[test]
+  fn test_changed_member() {
+    let original = archive();
+    let mut crushed = crushed(&original);
+    let offset = crushed_offset(&crushed, &header(b"b.bin", b'0', 10_000));
+    crushed[offset + 1000] ^= 1;
+    let expected = Divergence::ChangedMember { name: b"b.bin".to_vec(), original_offset: 2048, crushed_offset: offset };
+    assert_eq!(divergence(&original, &crushed), expected);
+  }
+
+  #[test]
+  fn test_missing_and_extra_members() {
+    let original = archive();
+    let extra = member(b"extra", b'0', b"extra");
+    let eoa = original.len() - END_OF_ARCHIVE.len();
+    let with_extra = [&original[..eoa], &extra, &END_OF_ARCHIVE].concat();
+    let expected = Divergence::MissingMember { name: b"extra".to_vec(), original_offset: eoa };
+    assert_eq!(divergence(&with_extra, &crushed(&original)), expected);
+    let crushed = crushed(&with_extra);
+    let crushed_offset = crushed_offset(&crushed, &header(b"extra", b'0', 5));
+    let expected = Divergence::ExtraMember { name: b"extra".to_vec(), crushed_offset };
+    assert_eq!(divergence(&original, &crushed), expected);
+  }
+
+  #[test]
+  fn test_moved_before_end_of_archive() {
+    // Restores byte for byte, but has a member that tar would have ignored
+    // moved to where it will be extracted.
+    let original = [archive(), member(b"after", b'0', b"after")].concat();
+    let mut crushed = original[..original.len() - 2048].to_vec();
+    crushed.extend_from_slice(&original[original.len() - 1024..]);
+    let frames = [0..original.len() - 2048, original.len() - 1024..original.len()];
+    restore::write_trailer(&mut crushed, &original, frames.into_iter()).unwrap();
+    let mut restored = Vec::new();
+    restore::restore(&crushed, &mut restored).unwrap();
+    assert_eq!(restored, original);
+    let crushed_offset = original.len() - 2048;
+    let expected = Divergence::ExtraMember { name: b"after".to_vec(), crushed_offset };
+    assert_eq!(divergence(&original, &crushed), expected);
+  }
+
+  #[test]
+  fn test_wrong_permutation() {
+    // Swap where the first two frames, which are the same length, are
+    // restored to: the members are all there, but in the wrong order.
+    let original = archive();
+    let mut crushed = crushed(&original);
+    let data_offset = crushed.len() - 512;
+    for i in 0..4 {
+      let entry = data_offset + 32 + 16 * i;
+      match u64::from_le_bytes(crushed[entry..entry + 8].try_into().unwrap()) {
+        0 => crushed[entry..entry + 8].copy_from_slice(&1024u64.to_le_bytes()),
+        1024 => crushed[entry..entry + 8].copy_from_slice(&0u64.to_le_bytes()),
+        _ => {}
+      }
+    }
+    // The names differ at their first byte.
+    assert_eq!(divergence(&original, &crushed), Divergence::RestoredBytes(0));
+  }
+
+  #[test]
+  fn test_not_crushed() {
+    let original = archive();
+    let result = verify(&original, &original);
+    assert!(matches!(result, Err(Error::MalformedCrushed(m)) if matches!(m.kind, Malformation::BadRestoreData(_))));
+    let mut truncated = original.clone();
+    truncated.truncate(1000);
+    assert!(matches!(verify(&truncated, &crushed(&original)), Err(Error::MalformedInput(_))));
+    let mut crushed = crushed(&original);
+    crushed[124..136].copy_from_slice(b"77777777777\0");
+    let Err(Error::MalformedCrushed(malformed)) = verify(&original, &crushed) else { panic!("expected MalformedCrushed") };
+    assert_eq!(malformed.member.as_deref(), Some(&b"a.txt"[..]));
+  }
+}